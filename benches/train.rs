@@ -0,0 +1,72 @@
+//! Times per-sample ED training of the MNIST models on synthetic 28x28 images, so storage
+//! changes to the layers can be compared. Run with `cargo bench`.
+
+use ed::{Mnist, MultiOutputLayer, PassThrough, Sigmoid};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::time::{Duration, Instant};
+
+const SAMPLES: usize = 2000;
+const LEARNING_RATE: f64 = 0.02;
+
+fn images(rng: &mut StdRng) -> Vec<(f64, Vec<f64>)> {
+    (0..SAMPLES)
+        .map(|i| {
+            let mut image = vec![0.; 784];
+            for row in 6..22 {
+                let start = rng.gen_range(6..14);
+                for column in start..start + 9 {
+                    image[row * 28 + column] = rng.gen::<f64>();
+                }
+            }
+            ((i % 2) as f64, image)
+        })
+        .collect()
+}
+
+fn report(name: &str, elapsed: Duration) {
+    println!(
+        "{:<24} {:>10.2?} total, {:>8.2?} per sample",
+        name,
+        elapsed,
+        elapsed / SAMPLES as u32
+    );
+}
+
+fn main() {
+    let data = images(&mut StdRng::seed_from_u64(1));
+
+    let mut mnist = Mnist::new(1, 4);
+    let start = Instant::now();
+    for (label, image) in data.iter() {
+        let output = mnist.forward(image);
+        let delta = 1. / (1. + (-output).exp()) - label;
+        mnist.backward(delta * LEARNING_RATE);
+    }
+    report("Mnist(1, 4)", start.elapsed());
+
+    for parallel in [false, true] {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut layer0 = MultiOutputLayer::<Sigmoid>::new(&mut rng, 10, 784 * 2, 4);
+        let mut last_layer = MultiOutputLayer::<PassThrough>::new(&mut rng, 10, 4, 1);
+        layer0.set_parallel(parallel);
+        last_layer.set_parallel(parallel);
+        let start = Instant::now();
+        for (label, image) in data.iter() {
+            let x: Vec<f64> = image.iter().flat_map(|&x| [x, x]).collect();
+            let x = layer0.forward(vec![x; 10]);
+            let outputs = last_layer.forward(x);
+            let deltas: Vec<f64> = outputs
+                .iter()
+                .map(|output| (output[0] - label) * LEARNING_RATE)
+                .collect();
+            layer0.backward(&deltas);
+            last_layer.backward(&deltas);
+        }
+        let name = if parallel {
+            "MultiOutputLayer x10 par"
+        } else {
+            "MultiOutputLayer x10"
+        };
+        report(name, start.elapsed());
+    }
+}
@@ -1,6 +1,15 @@
+pub(super) mod alignment;
 pub(super) mod differentiable_fn;
+pub(super) mod float;
 pub(super) mod gate;
+pub(super) mod initializer;
 pub(super) mod layer;
 pub(super) mod loss_fn;
 pub(super) mod mnist;
+pub(super) mod optimizer;
+pub(super) mod persistence;
+pub(super) mod recurrent;
+pub(super) mod schedule;
+pub(super) mod sequential;
+pub(super) mod trainer;
 pub(super) mod util;
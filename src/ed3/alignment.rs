@@ -0,0 +1,56 @@
+/// How close the ED update of some weights is to plain gradient descent on the same sample.
+/// Like the losses, it is computed in `f64` whatever the precision of the weights.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Alignment {
+    /// Cosine of the angle between the two updates, zero if either of them is zero.
+    pub cosine_similarity: f64,
+    /// Fraction of the weights that both updates move in the same direction, or leave alone.
+    pub sign_agreement: f64,
+}
+
+impl Alignment {
+    /// Compares the ED changes of some weights with their negative backprop gradients.
+    pub fn new(ed: &[f64], bp: &[f64]) -> Self {
+        assert_eq!(ed.len(), bp.len());
+        let dot: f64 = ed.iter().zip(bp).map(|(ed, bp)| ed * bp).sum();
+        let norms = norm(ed) * norm(bp);
+        let agreeing = ed
+            .iter()
+            .zip(bp)
+            .filter(|(&ed, &bp)| ed.partial_cmp(&0.) == bp.partial_cmp(&0.))
+            .count();
+        Alignment {
+            cosine_similarity: if norms == 0. { 0. } else { dot / norms },
+            sign_agreement: agreeing as f64 / ed.len() as f64,
+        }
+    }
+}
+
+fn norm(x: &[f64]) -> f64 {
+    x.iter().map(|x| x * x).sum::<f64>().sqrt()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerAlignment {
+    /// Over all the weights of the layer, bias weights included.
+    pub layer: Alignment,
+    /// Over the incoming weights of every output neuron.
+    pub neurons: Vec<Alignment>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alignment() {
+        let alignment = Alignment::new(&[1., 0., -2.], &[2., 0., -4.]);
+        assert!((alignment.cosine_similarity - 1.).abs() < 1e-12);
+        assert_eq!(alignment.sign_agreement, 1.);
+
+        let alignment = Alignment::new(&[1., 0.], &[0., 1.]);
+        assert_eq!(alignment.cosine_similarity, 0.);
+        assert_eq!(alignment.sign_agreement, 0.);
+        assert_eq!(Alignment::new(&[0., 0.], &[1., -1.]).cosine_similarity, 0.);
+    }
+}
@@ -1,32 +1,349 @@
-use std::f64::consts::E;
+use super::{float::Float, persistence::PersistentActivation};
+use num_traits::clamp;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::{E, FRAC_2_SQRT_PI, SQRT_2};
 
-pub trait DifferentiableFn: Send {
+/// `F` is the precision of the values, activations take `Args = F`.
+pub trait DifferentiableFn<F = f64>: Send + Sync {
     type Args;
-    fn eval(input: Self::Args) -> f64;
-    fn derivative(input: Self::Args) -> f64;
+    fn eval(&self, input: Self::Args) -> F;
+    fn derivative(&self, input: Self::Args) -> F;
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
 pub struct PassThrough;
-impl DifferentiableFn for PassThrough {
-    type Args = f64;
-    fn eval(input: Self::Args) -> f64 {
+impl<F: Float> DifferentiableFn<F> for PassThrough {
+    type Args = F;
+    fn eval(&self, input: Self::Args) -> F {
         input
     }
-    fn derivative(_: Self::Args) -> f64 {
-        1.0
+    fn derivative(&self, _: Self::Args) -> F {
+        F::one()
     }
 }
+impl PersistentActivation for PassThrough {
+    const NAME: &'static str = "PassThrough";
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Sigmoid {
+    pub gain: f64,
+}
+impl Sigmoid {
+    pub fn new(gain: f64) -> Self {
+        Sigmoid { gain }
+    }
+
+    /// A gain of `2 / u0`, the sigmoid `1 / (1 + exp(-2 * input / u0))` of `Recurrent` up to
+    /// rounding.
+    pub fn with_u0(u0: f64) -> Self {
+        Sigmoid::new(2. / u0)
+    }
+}
+impl Default for Sigmoid {
+    fn default() -> Self {
+        Sigmoid::new(1.0)
+    }
+}
+impl<F: Float> DifferentiableFn<F> for Sigmoid {
+    type Args = F;
+    fn eval(&self, input: Self::Args) -> F {
+        F::one() / (F::one() + F::cast(E).powf(-F::cast(self.gain) * input))
+    }
+    fn derivative(&self, input: Self::Args) -> F {
+        let s = self.eval(input);
+        F::cast(self.gain) * s * (F::one() - s)
+    }
+}
+impl PersistentActivation for Sigmoid {
+    const NAME: &'static str = "Sigmoid";
+}
+
+/// `slope * input` clipped to `[min, max]`, with zero derivative outside the range.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ClippedLinear {
+    pub slope: f64,
+    pub min: f64,
+    pub max: f64,
+}
+impl ClippedLinear {
+    pub fn new(slope: f64, min: f64, max: f64) -> Self {
+        ClippedLinear { slope, min, max }
+    }
+}
+impl<F: Float> DifferentiableFn<F> for ClippedLinear {
+    type Args = F;
+    fn eval(&self, input: Self::Args) -> F {
+        clamp(
+            F::cast(self.slope) * input,
+            F::cast(self.min),
+            F::cast(self.max),
+        )
+    }
+    fn derivative(&self, input: Self::Args) -> F {
+        let output = F::cast(self.slope) * input;
+        if F::cast(self.min) < output && output < F::cast(self.max) {
+            F::cast(self.slope)
+        } else {
+            F::zero()
+        }
+    }
+}
+impl PersistentActivation for ClippedLinear {
+    const NAME: &'static str = "ClippedLinear";
+}
 
-#[derive(Debug)]
-pub struct Sigmoid;
-impl DifferentiableFn for Sigmoid {
-    type Args = f64;
-    fn eval(input: Self::Args) -> f64 {
-        1.0 / (1.0 + E.powf(-input))
+// The logistic function without overflow for inputs of large magnitude.
+pub(super) fn logistic<F: Float>(input: F) -> F {
+    if input >= F::zero() {
+        F::one() / (F::one() + (-input).exp())
+    } else {
+        let exp = input.exp();
+        exp / (F::one() + exp)
     }
-    fn derivative(input: Self::Args) -> f64 {
-        let s = Sigmoid::eval(input);
-        s * (1.0 - s)
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Relu;
+impl<F: Float> DifferentiableFn<F> for Relu {
+    type Args = F;
+    fn eval(&self, input: Self::Args) -> F {
+        input.max(F::zero())
+    }
+    fn derivative(&self, input: Self::Args) -> F {
+        if input > F::zero() {
+            F::one()
+        } else {
+            F::zero()
+        }
+    }
+}
+impl PersistentActivation for Relu {
+    const NAME: &'static str = "Relu";
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct LeakyRelu {
+    pub slope: f64,
+}
+impl LeakyRelu {
+    pub fn new(slope: f64) -> Self {
+        LeakyRelu { slope }
+    }
+}
+impl Default for LeakyRelu {
+    fn default() -> Self {
+        LeakyRelu::new(0.01)
+    }
+}
+impl<F: Float> DifferentiableFn<F> for LeakyRelu {
+    type Args = F;
+    fn eval(&self, input: Self::Args) -> F {
+        if input > F::zero() {
+            input
+        } else {
+            F::cast(self.slope) * input
+        }
+    }
+    fn derivative(&self, input: Self::Args) -> F {
+        if input > F::zero() {
+            F::one()
+        } else {
+            F::cast(self.slope)
+        }
+    }
+}
+impl PersistentActivation for LeakyRelu {
+    const NAME: &'static str = "LeakyRelu";
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Tanh;
+impl<F: Float> DifferentiableFn<F> for Tanh {
+    type Args = F;
+    fn eval(&self, input: Self::Args) -> F {
+        input.tanh()
+    }
+    fn derivative(&self, input: Self::Args) -> F {
+        F::one() - input.tanh().powi(2)
+    }
+}
+impl PersistentActivation for Tanh {
+    const NAME: &'static str = "Tanh";
+}
+
+/// `ln(1 + exp(input))`, a smooth ReLU.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Softplus;
+impl<F: Float> DifferentiableFn<F> for Softplus {
+    type Args = F;
+    fn eval(&self, input: Self::Args) -> F {
+        input.max(F::zero()) + (-input.abs()).exp().ln_1p()
+    }
+    fn derivative(&self, input: Self::Args) -> F {
+        logistic(input)
+    }
+}
+impl PersistentActivation for Softplus {
+    const NAME: &'static str = "Softplus";
+}
+
+/// `input / 6 + 0.5` clipped to `[0, 1]`, as in PyTorch.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct HardSigmoid;
+impl<F: Float> DifferentiableFn<F> for HardSigmoid {
+    type Args = F;
+    fn eval(&self, input: Self::Args) -> F {
+        clamp(input / F::cast(6.) + F::cast(0.5), F::zero(), F::one())
+    }
+    fn derivative(&self, input: Self::Args) -> F {
+        if F::cast(-3.) < input && input < F::cast(3.) {
+            F::cast(1. / 6.)
+        } else {
+            F::zero()
+        }
+    }
+}
+impl PersistentActivation for HardSigmoid {
+    const NAME: &'static str = "HardSigmoid";
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Elu {
+    pub alpha: f64,
+}
+impl Elu {
+    pub fn new(alpha: f64) -> Self {
+        Elu { alpha }
+    }
+}
+impl Default for Elu {
+    fn default() -> Self {
+        Elu::new(1.)
+    }
+}
+impl<F: Float> DifferentiableFn<F> for Elu {
+    type Args = F;
+    fn eval(&self, input: Self::Args) -> F {
+        if input > F::zero() {
+            input
+        } else {
+            F::cast(self.alpha) * input.exp_m1()
+        }
+    }
+    fn derivative(&self, input: Self::Args) -> F {
+        if input > F::zero() {
+            F::one()
+        } else {
+            F::cast(self.alpha) * input.exp()
+        }
+    }
+}
+impl PersistentActivation for Elu {
+    const NAME: &'static str = "Elu";
+}
+
+/// The tanh approximation of GELU, `input` times the Gaussian CDF.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Gelu;
+impl Gelu {
+    // Beyond this magnitude GELU is the identity or zero within the precision of f64, and the
+    // cube would overflow.
+    const LIMIT: f64 = 10.;
+    // sqrt(2 / pi)
+    const SCALE: f64 = FRAC_2_SQRT_PI / SQRT_2;
+
+    fn inner<F: Float>(input: F) -> F {
+        F::cast(Self::SCALE) * (input + F::cast(0.044715) * input.powi(3))
+    }
+}
+impl<F: Float> DifferentiableFn<F> for Gelu {
+    type Args = F;
+    fn eval(&self, input: Self::Args) -> F {
+        if input.abs() > F::cast(Self::LIMIT) {
+            return input.max(F::zero());
+        }
+        F::cast(0.5) * input * (F::one() + Self::inner(input).tanh())
+    }
+    fn derivative(&self, input: Self::Args) -> F {
+        if input.abs() > F::cast(Self::LIMIT) {
+            return if input > F::zero() {
+                F::one()
+            } else {
+                F::zero()
+            };
+        }
+        let tanh = Self::inner(input).tanh();
+        let inner_derivative =
+            F::cast(Self::SCALE) * (F::one() + F::cast(3. * 0.044715) * input.powi(2));
+        let half = F::cast(0.5);
+        half * (F::one() + tanh) + half * input * (F::one() - tanh * tanh) * inner_derivative
+    }
+}
+impl PersistentActivation for Gelu {
+    const NAME: &'static str = "Gelu";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sigmoid_gain() {
+        let sigmoid = Sigmoid::new(5.0);
+        assert_eq!(sigmoid.eval(0.2), Sigmoid::default().eval(1.0));
+        assert_eq!(sigmoid.derivative(0.0), 1.25);
+    }
+
+    #[test]
+    fn test_sigmoid_u0() {
+        let sigmoid = Sigmoid::with_u0(0.4);
+        let input: f64 = 0.3;
+        assert!((sigmoid.eval(input) - 1. / (1. + (-2. * input / 0.4).exp())).abs() < 1e-15);
+    }
+
+    fn check_activation<F>(f: F)
+    where
+        F: DifferentiableFn<Args = f64>,
+    {
+        let h = 1e-6;
+        for input in [-2.7, -0.6, -0.1, 0.2, 0.9, 2.4] {
+            let numeric = (f.eval(input + h) - f.eval(input - h)) / (2. * h);
+            assert!((numeric - f.derivative(input)).abs() < 1e-6, "at {}", input);
+        }
+        for input in [-1e300, -800., 800., 1e300] {
+            assert!(f.eval(input).is_finite() && f.derivative(input).is_finite());
+        }
+    }
+
+    #[test]
+    fn test_activations() {
+        check_activation(Relu);
+        check_activation(LeakyRelu::default());
+        check_activation(Tanh);
+        check_activation(Softplus);
+        check_activation(HardSigmoid);
+        check_activation(Elu::default());
+        check_activation(Gelu);
+        check_activation(Sigmoid::default());
+
+        assert_eq!(Relu.eval(-3.), 0.);
+        assert_eq!(LeakyRelu::new(0.1).eval(-3.), -0.30000000000000004);
+        assert_eq!(Softplus.eval(800.), 800.);
+        assert_eq!(Softplus.eval(0.), 2f64.ln());
+        assert_eq!(HardSigmoid.eval(-4.), 0.);
+        assert_eq!(Elu::default().eval(-800.), -1.);
+        assert_eq!(Gelu.eval(-20.), 0.);
+        assert!((Gelu.eval(1f64) - 0.8411919906082768).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_clipped_linear() {
+        let f = ClippedLinear::new(2.0, -1.0, 1.0);
+        assert_eq!(f.eval(0.25), 0.5);
+        assert_eq!(f.eval(3.0), 1.0);
+        assert_eq!(f.eval(-3.0), -1.0);
+        assert_eq!(f.derivative(0.25), 2.0);
+        assert_eq!(f.derivative(3.0), 0.0);
     }
 }
@@ -0,0 +1,55 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    fmt::{Debug, Display},
+    iter::Sum,
+    ops::{AddAssign, MulAssign, SubAssign},
+};
+
+/// Precision of the weights, activations and losses, `f32` or `f64`. Parameters such as
+/// learning rates and configs stay `f64` and are rounded with `cast` where they are applied.
+/// The layers, the models, the losses, the optimizers and `Trainer` are generic over it.
+pub trait Float:
+    num_traits::Float
+    + Default
+    + Debug
+    + Display
+    + Sum
+    + for<'a> Sum<&'a Self>
+    + AddAssign
+    + SubAssign
+    + MulAssign
+    + Send
+    + Sync
+    + Serialize
+    + DeserializeOwned
+    + 'static
+{
+    /// Identifies the precision in model files.
+    const NAME: &'static str;
+
+    /// Rounds `value` to this precision, exact for `f64`.
+    fn cast(value: f64) -> Self;
+    fn as_f64(self) -> f64;
+}
+
+impl Float for f32 {
+    const NAME: &'static str = "f32";
+
+    fn cast(value: f64) -> Self {
+        value as f32
+    }
+    fn as_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Float for f64 {
+    const NAME: &'static str = "f64";
+
+    fn cast(value: f64) -> Self {
+        value
+    }
+    fn as_f64(self) -> f64 {
+        self
+    }
+}
@@ -1,42 +1,230 @@
 use super::{
+    alignment::LayerAlignment,
     differentiable_fn::{DifferentiableFn, Sigmoid},
-    layer::Layer,
+    float::Float,
+    layer::{BatchReduction, Layer, LayerState, LayerStateV1},
+    persistence::{check_dims, ModelError, Persist, PersistentActivation, VersionedState},
+    schedule::{ModelSchedule, Schedule},
+    trainer::Model,
 };
 use rand::{rngs::StdRng, SeedableRng};
+use serde::{Deserialize, Serialize};
 
-pub struct Gate<LastActivation>
+/// A two-input logic gate network of three layers, the inputs being duplicated.
+pub struct Gate<LastActivation, F = f64>
 where
-    LastActivation: DifferentiableFn<Args = f64>,
+    LastActivation: DifferentiableFn<F, Args = F>,
+    F: Float,
 {
-    layer0: Layer<Sigmoid>,
-    layer1: Layer<Sigmoid>,
-    layer2: Layer<LastActivation>,
+    layer0: Layer<Sigmoid, F>,
+    layer1: Layer<Sigmoid, F>,
+    layer2: Layer<LastActivation, F>,
+    schedule: ModelSchedule,
 }
 
-impl<LastActivation> Gate<LastActivation>
+impl<LastActivation, F> Gate<LastActivation, F>
 where
-    LastActivation: DifferentiableFn<Args = f64>,
+    LastActivation: DifferentiableFn<F, Args = F>,
+    F: Float,
 {
-    pub fn new() -> Self {
+    pub fn new() -> Self
+    where
+        LastActivation: Default,
+    {
         let mut rng = StdRng::seed_from_u64(42);
         Gate {
             layer0: Layer::new(&mut rng, 4, 8),
             layer1: Layer::new(&mut rng, 8, 8),
             layer2: Layer::new(&mut rng, 8, 1),
+            schedule: ModelSchedule::default(),
         }
     }
 
-    pub fn forward(&mut self, inputs: &[f64]) -> f64 {
+    /// The deltas passed to `backward` are scaled by the rate of `schedule`, which the caller
+    /// advances with `step_schedule`.
+    pub fn set_schedule<S>(&mut self, schedule: S)
+    where
+        S: Schedule + 'static,
+    {
+        self.schedule.set(schedule);
+    }
+
+    pub fn learning_rate(&self) -> Option<f64> {
+        self.schedule.learning_rate()
+    }
+
+    pub fn step_schedule(&mut self, metric: Option<f64>) {
+        self.schedule.step(metric);
+    }
+
+    pub fn forward(&mut self, inputs: &[F]) -> F {
         let x = vec![inputs[0], inputs[0], inputs[1], inputs[1]];
         let x = self.layer0.forward(x);
         let x = self.layer1.forward(x);
         self.layer2.forward(x)[0]
     }
 
-    pub fn backward(&mut self, delta: f64) {
-        self.layer0.backward(delta);
-        self.layer1.backward(delta);
-        self.layer2.backward(delta);
+    pub fn forward_without_train(&self, inputs: &[F]) -> F {
+        let x = vec![inputs[0], inputs[0], inputs[1], inputs[1]];
+        let x = self.layer0.forward_without_train(x);
+        let x = self.layer1.forward_without_train(x);
+        self.layer2.forward_without_train(x)[0]
+    }
+
+    pub fn backward(&mut self, delta: F) {
+        let rate = self.schedule.rate();
+        self.layer0.backward_with_rate(delta, rate);
+        self.layer1.backward_with_rate(delta, rate);
+        self.layer2.backward_with_rate(delta, rate);
+    }
+
+    /// Compares the update `backward` would make for one sample with backprop, layer by layer,
+    /// see `Sequential::gradient_alignment`.
+    pub fn gradient_alignment(&self, inputs: &[F], delta: F) -> Vec<LayerAlignment> {
+        let x0 = vec![inputs[0], inputs[0], inputs[1], inputs[1]];
+        let x1 = self.layer0.forward_without_train(x0.clone());
+        let x2 = self.layer1.forward_without_train(x1.clone());
+        let (alignment2, gradient) = self.layer2.gradient_alignment(&x2, delta, &[delta]);
+        let (alignment1, gradient) = self.layer1.gradient_alignment(&x1, delta, &gradient);
+        let (alignment0, _) = self.layer0.gradient_alignment(&x0, delta, &gradient);
+        vec![alignment0, alignment1, alignment2]
+    }
+
+    pub fn forward_batch(&mut self, inputs: &[&[F]]) -> Vec<F> {
+        let x = inputs
+            .iter()
+            .map(|inputs| vec![inputs[0], inputs[0], inputs[1], inputs[1]])
+            .collect();
+        let x = self.layer0.forward_batch(x);
+        let x = self.layer1.forward_batch(x);
+        self.layer2
+            .forward_batch(x)
+            .into_iter()
+            .map(|x| x[0])
+            .collect()
+    }
+
+    pub fn backward_batch(&mut self, deltas: &[F], reduction: BatchReduction) {
+        let rate = self.schedule.rate();
+        self.layer0
+            .backward_batch_with_rate(deltas, reduction, rate);
+        self.layer1
+            .backward_batch_with_rate(deltas, reduction, rate);
+        self.layer2
+            .backward_batch_with_rate(deltas, reduction, rate);
+    }
+}
+
+impl<LastActivation, F> Default for Gate<LastActivation, F>
+where
+    LastActivation: DifferentiableFn<F, Args = F> + Default,
+    F: Float,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<LastActivation, F> Model<F> for Gate<LastActivation, F>
+where
+    LastActivation: DifferentiableFn<F, Args = F>,
+    F: Float,
+{
+    type Output = F;
+
+    fn forward(&mut self, inputs: &[F]) -> F {
+        Gate::forward(self, inputs)
+    }
+    fn forward_without_train(&self, inputs: &[F]) -> F {
+        Gate::forward_without_train(self, inputs)
+    }
+    fn backward(&mut self, &delta: &F) {
+        Gate::backward(self, delta);
+    }
+    fn learning_rate(&self) -> Option<f64> {
+        Gate::learning_rate(self)
+    }
+    fn step_schedule(&mut self, metric: Option<f64>) {
+        Gate::step_schedule(self, metric);
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(bound(deserialize = "LastActivation: Deserialize<'de>, F: Float"))]
+pub struct GateState<LastActivation, F = f64> {
+    layer0: LayerState<Sigmoid, F>,
+    layer1: LayerState<Sigmoid, F>,
+    layer2: LayerState<LastActivation, F>,
+}
+
+#[derive(Deserialize)]
+#[serde(bound(deserialize = "LastActivation: Deserialize<'de>, F: Float"))]
+pub struct GateStateV1<LastActivation, F> {
+    layer0: LayerStateV1<Sigmoid, F>,
+    layer1: LayerStateV1<Sigmoid, F>,
+    layer2: LayerStateV1<LastActivation, F>,
+}
+
+impl<LastActivation, F> VersionedState for GateState<LastActivation, F>
+where
+    LastActivation: PersistentActivation,
+    F: Float,
+{
+    type V1 = GateStateV1<LastActivation, F>;
+
+    fn from_v1(state: Self::V1) -> Self {
+        GateState {
+            layer0: LayerState::from_v1(state.layer0),
+            layer1: LayerState::from_v1(state.layer1),
+            layer2: LayerState::from_v1(state.layer2),
+        }
+    }
+}
+
+impl<LastActivation, F> Persist for Gate<LastActivation, F>
+where
+    LastActivation: DifferentiableFn<F, Args = F> + PersistentActivation + Clone,
+    F: Float,
+{
+    const MODEL: &'static str = "Gate";
+    const PRECISION: &'static str = F::NAME;
+    type State = GateState<LastActivation, F>;
+
+    fn activations() -> Vec<&'static str> {
+        vec![Sigmoid::NAME, Sigmoid::NAME, LastActivation::NAME]
+    }
+
+    fn state(&self) -> Self::State {
+        GateState {
+            layer0: self.layer0.state(),
+            layer1: self.layer1.state(),
+            layer2: self.layer2.state(),
+        }
+    }
+
+    fn from_state(state: Self::State) -> Result<Self, ModelError> {
+        let gate = Gate {
+            layer0: Layer::from_state(state.layer0)?,
+            layer1: Layer::from_state(state.layer1)?,
+            layer2: Layer::from_state(state.layer2)?,
+            schedule: ModelSchedule::default(),
+        };
+        check_dims(
+            "layer0",
+            (gate.layer0.input(), gate.layer0.output()),
+            (4, 8),
+        )?;
+        check_dims(
+            "layer1",
+            (gate.layer1.input(), gate.layer1.output()),
+            (8, 8),
+        )?;
+        check_dims(
+            "layer2",
+            (gate.layer2.input(), gate.layer2.output()),
+            (8, 1),
+        )?;
+        Ok(gate)
     }
 }
 
@@ -45,6 +233,7 @@ mod tests {
     use super::super::{
         differentiable_fn::{DifferentiableFn, PassThrough},
         loss_fn::{BCELoss, BCEWithLogitsLoss, MSELoss},
+        schedule::StepDecay,
     };
     use super::*;
 
@@ -62,10 +251,10 @@ mod tests {
 
             for (inputs, &target) in inputs.iter().zip(targets.iter()) {
                 let output = xor.forward(inputs);
-                let delta = BCELoss::derivative((output, target));
+                let delta = BCELoss.derivative((output, target));
                 xor.backward(delta * LEARNING_RATE);
 
-                loss += BCELoss::eval((output, target)).abs();
+                loss += BCELoss.eval((output, target)).abs();
             }
 
             println!("loss: {:.8}", loss / 4.);
@@ -92,10 +281,10 @@ mod tests {
 
             for (inputs, &target) in inputs.iter().zip(targets.iter()) {
                 let output = xor.forward(inputs);
-                let delta = MSELoss::derivative((output, target));
+                let delta = MSELoss.derivative((output, target));
                 xor.backward(delta * LEARNING_RATE);
 
-                loss += MSELoss::eval((output, target)).abs();
+                loss += MSELoss.eval((output, target)).abs();
             }
 
             println!("loss: {:.8}", loss / 4.);
@@ -122,10 +311,10 @@ mod tests {
 
             for (inputs, &target) in inputs.iter().zip(targets.iter()) {
                 let output = xor.forward(inputs);
-                let delta = BCEWithLogitsLoss::derivative((output, target));
+                let delta = BCEWithLogitsLoss.derivative((output, target));
                 xor.backward(delta * LEARNING_RATE);
 
-                loss += BCEWithLogitsLoss::eval((output, target)).abs();
+                loss += BCEWithLogitsLoss.eval((output, target)).abs();
             }
 
             println!("loss: {:.8}", loss / 4.);
@@ -137,9 +326,27 @@ mod tests {
                 "{}, {} -> {:.8}, {:.0}",
                 inputs[0],
                 inputs[1],
-                Sigmoid::eval(output),
+                Sigmoid::default().eval(output),
                 target
             );
         }
     }
+
+    #[test]
+    fn test_schedule() {
+        let mut xor = Gate::<Sigmoid>::new();
+        let mut scheduled_xor = Gate::<Sigmoid>::new();
+        scheduled_xor.set_schedule(StepDecay::new(LEARNING_RATE, 0.5, 4));
+
+        for i in 0..8 {
+            let inputs = [(i % 2) as f64, (i / 2 % 2) as f64];
+            let delta = xor.forward(&inputs) - (i % 3 == 0) as u8 as f64;
+            scheduled_xor.forward(&inputs);
+            xor.backward(delta * LEARNING_RATE * 0.5f64.powi(i / 4));
+            scheduled_xor.backward(delta);
+            scheduled_xor.step_schedule(None);
+        }
+        assert_eq!(scheduled_xor.learning_rate(), Some(LEARNING_RATE * 0.25));
+        assert_eq!(scheduled_xor.forward(&[1., 0.]), xor.forward(&[1., 0.]));
+    }
 }
@@ -0,0 +1,114 @@
+use rand::Rng;
+use rand_distr::{Distribution, Normal};
+
+/// Draws the magnitude of initial weights, the excitatory/inhibitory sign is applied on top.
+/// `fan_in` and `fan_out` are the input and output widths of the layer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Initializer {
+    /// Uniform in `[min, max)`.
+    Uniform {
+        min: f64,
+        max: f64,
+    },
+    /// Uniform in `[0, scale / sqrt(fan_in))`.
+    FanIn {
+        scale: f64,
+    },
+    /// Glorot uniform, in `[0, sqrt(6 / (fan_in + fan_out)))`.
+    Xavier,
+    /// He normal as in `src/bp.rs`, the absolute value of `N(0, 2 / fan_in)`.
+    Kaiming,
+    Constant(f64),
+}
+
+impl Default for Initializer {
+    fn default() -> Self {
+        Initializer::Uniform { min: 0., max: 1. }
+    }
+}
+
+impl Initializer {
+    // Magnitudes must not be negative, or they would flip the excitatory/inhibitory signs.
+    pub(super) fn validate(&self) {
+        match *self {
+            Initializer::Uniform { min, max } => {
+                assert!(0. <= min && min <= max, "Uniform needs 0 <= min <= max")
+            }
+            Initializer::FanIn { scale } => assert!(scale >= 0., "scale must not be negative"),
+            Initializer::Constant(value) => assert!(value >= 0., "Constant must not be negative"),
+            Initializer::Xavier | Initializer::Kaiming => {}
+        }
+    }
+
+    pub fn magnitude<R>(&self, rng: &mut R, fan_in: usize, fan_out: usize) -> f64
+    where
+        R: Rng,
+    {
+        let uniform = |rng: &mut R, max: f64| max * rng.gen::<f64>();
+        match *self {
+            Initializer::Uniform { min, max } => min + uniform(rng, max - min),
+            Initializer::FanIn { scale } => uniform(rng, scale / (fan_in as f64).sqrt()),
+            Initializer::Xavier => uniform(rng, (6. / (fan_in + fan_out) as f64).sqrt()),
+            Initializer::Kaiming => {
+                let std_dev = (2. / fan_in as f64).sqrt();
+                Normal::new(0., std_dev).unwrap().sample(rng).abs()
+            }
+            Initializer::Constant(value) => value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn magnitudes(initializer: Initializer, fan_in: usize, fan_out: usize) -> Vec<f64> {
+        let mut rng = StdRng::seed_from_u64(42);
+        (0..1000)
+            .map(|_| initializer.magnitude(&mut rng, fan_in, fan_out))
+            .collect()
+    }
+
+    #[test]
+    fn test_ranges() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let expected: Vec<f64> = (0..1000).map(|_| rng.gen()).collect();
+        assert_eq!(magnitudes(Initializer::default(), 4, 4), expected);
+
+        let within = |magnitudes: Vec<f64>, min: f64, max: f64| {
+            magnitudes.iter().all(|&m| min <= m && m < max)
+        };
+        let uniform = Initializer::Uniform { min: 0.2, max: 0.5 };
+        assert!(within(magnitudes(uniform, 4, 4), 0.2, 0.5));
+        let fan_in = Initializer::FanIn { scale: 1. };
+        assert!(within(magnitudes(fan_in, 100, 4), 0., 0.1));
+        assert!(within(magnitudes(Initializer::Xavier, 100, 50), 0., 0.2));
+        assert_eq!(
+            magnitudes(Initializer::Constant(0.3), 4, 4),
+            vec![0.3; 1000]
+        );
+
+        let kaiming = magnitudes(Initializer::Kaiming, 200, 4);
+        let mean = kaiming.iter().sum::<f64>() / kaiming.len() as f64;
+        // The mean of a half-normal distribution is `std_dev * sqrt(2 / pi)`.
+        let expected = 0.1 * (2. / std::f64::consts::PI).sqrt();
+        assert!(kaiming.iter().all(|&m| m >= 0.) && (mean - expected).abs() < 0.01);
+    }
+
+    #[test]
+    #[should_panic(expected = "0 <= min <= max")]
+    fn test_negative_uniform() {
+        Initializer::Uniform {
+            min: -0.5,
+            max: 0.5,
+        }
+        .validate();
+    }
+
+    #[test]
+    #[should_panic(expected = "Constant must not be negative")]
+    fn test_negative_constant() {
+        Initializer::Constant(-0.1).validate();
+    }
+}
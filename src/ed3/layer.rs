@@ -1,239 +1,2288 @@
-use super::differentiable_fn::DifferentiableFn;
-use rand::Rng;
-use std::marker::PhantomData;
+use super::{
+    alignment::{Alignment, LayerAlignment},
+    differentiable_fn::DifferentiableFn,
+    float::Float,
+    initializer::Initializer,
+    optimizer::Optimizer,
+    persistence::{ModelError, Persist, PersistentActivation, VersionedState},
+    schedule::{ModelSchedule, Schedule},
+    util::mix_seed,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rand_distr::StandardNormal;
+use rayon::prelude::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::ops::Mul;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LayerConfig {
+    /// Constant input of the bias pair of every output neuron, like `BETA` in `src/ed.rs`: one
+    /// input trained by the positive amine, one by the negative amine. `None` disables the bias.
+    pub bias: Option<f64>,
+    pub sign_constraint: SignConstraint,
+    pub weight_decay: WeightDecay,
+    pub initializer: Initializer,
+    pub layout: Layout,
+    pub noise: Noise,
+}
+
+/// Stochastic regularization, applied by `forward` and `forward_batch` only. The generator is
+/// seeded from the one the layer is initialized with. Dropped units are zeroed and the kept
+/// ones scaled by `1 / (1 - rate)`, so `forward_without_train` needs no rescaling.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Noise {
+    /// Probability to drop every input, whose synapses are then left untouched by `backward`.
+    pub input_dropout: f64,
+    /// Probability to drop every output, whose synapses are then left untouched by `backward`.
+    pub output_dropout: f64,
+    /// Standard deviation of the Gaussian noise added to the inputs before the dropout. Noisy
+    /// inputs are clamped at zero, non-negative inputs stay non-negative.
+    pub input_std: f64,
+}
+
+impl Noise {
+    fn is_zero(&self) -> bool {
+        self.input_dropout == 0. && self.output_dropout == 0. && self.input_std == 0.
+    }
+
+    fn validate(&self) {
+        assert!(
+            (0. ..1.).contains(&self.input_dropout),
+            "input_dropout must be in [0, 1)"
+        );
+        assert!(
+            (0. ..1.).contains(&self.output_dropout),
+            "output_dropout must be in [0, 1)"
+        );
+        assert!(self.input_std >= 0., "input_std must not be negative");
+    }
+}
+
+// Zeroes every value with probability `rate` and scales the kept ones, returns the kept mask.
+fn drop_out<R, F>(rng: &mut R, rate: f64, values: &mut [F]) -> Vec<bool>
+where
+    R: Rng,
+    F: Float,
+{
+    let scale = F::cast(1. / (1. - rate));
+    values
+        .iter_mut()
+        .map(|value| {
+            let kept = rng.gen::<f64>() >= rate;
+            *value = if kept { *value * scale } else { F::zero() };
+            kept
+        })
+        .collect()
+}
+
+/// Assigns excitatory or inhibitory to every neuron from its index in a layer of `width`
+/// neurons. A synapse is excitatory between neurons of the same type, and the positive amine
+/// trains the synapses from excitatory inputs, the negative one those from inhibitory inputs.
+/// The layers of a network stay consistent as long as they share the layout. `Encoder::Duplicate`
+/// pairs every input with an excitatory and an inhibitory copy under `Parity` only.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Layout {
+    /// Even indices are excitatory, like `src/ed.rs`.
+    #[default]
+    Parity,
+    /// Repeats `excitatory` excitatory neurons followed by `inhibitory` inhibitory ones.
+    Ratio {
+        excitatory: usize,
+        inhibitory: usize,
+    },
+    /// Every index is excitatory with the given probability, drawn from a generator seeded with
+    /// `seed` mixed with the index.
+    Random { excitatory_fraction: f64, seed: u64 },
+    /// The first `excitatory_fraction` of the layer is excitatory, the rest inhibitory.
+    Blocks { excitatory_fraction: f64 },
+}
+
+impl Layout {
+    pub fn is_excitatory(&self, index: usize, width: usize) -> bool {
+        match *self {
+            Layout::Parity => index.is_multiple_of(2),
+            Layout::Ratio {
+                excitatory,
+                inhibitory,
+            } => {
+                assert!(excitatory + inhibitory > 0);
+                index % (excitatory + inhibitory) < excitatory
+            }
+            Layout::Random {
+                excitatory_fraction,
+                seed,
+            } => {
+                let mut rng = StdRng::seed_from_u64(mix_seed(seed, index as u64));
+                rng.gen::<f64>() < excitatory_fraction
+            }
+            Layout::Blocks {
+                excitatory_fraction,
+            } => index < (width as f64 * excitatory_fraction).round() as usize,
+        }
+    }
+
+    fn validate(&self) {
+        match *self {
+            Layout::Parity => {}
+            Layout::Ratio {
+                excitatory,
+                inhibitory,
+            } => assert!(excitatory + inhibitory > 0, "Ratio needs a neuron"),
+            Layout::Random {
+                excitatory_fraction,
+                ..
+            }
+            | Layout::Blocks {
+                excitatory_fraction,
+            } => assert!(
+                (0. ..=1.).contains(&excitatory_fraction),
+                "excitatory_fraction must be in [0, 1]"
+            ),
+        }
+    }
+
+    fn types(&self, width: usize) -> Vec<NeuronType> {
+        (0..width)
+            .map(|index| NeuronType::new(self.is_excitatory(index, width)))
+            .collect()
+    }
+}
+
+/// Shrinks the magnitude of every weight after each update, first by the factor `1 - l2`, then
+/// by `l1` down to zero at most. No weight changes sign, so excitatory and inhibitory weights
+/// stay what they are. The bias weights are not decayed.
+///
+/// An update is one `backward` call or one whole `backward_batch`, so a mini-batch of `n`
+/// samples decays once where `n` calls to `backward` decay `n` times. The decay doesn't follow
+/// the learning rate either, scaling the deltas or a schedule leaves it unchanged; lower `l1` and
+/// `l2` along with the rate when that matters.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct WeightDecay {
+    pub l1: f64,
+    pub l2: f64,
+}
+
+impl WeightDecay {
+    fn is_zero(&self) -> bool {
+        self.l1 == 0. && self.l2 == 0.
+    }
+
+    fn validate(&self) {
+        assert!(self.l1 >= 0., "l1 must not be negative");
+        assert!((0. ..1.).contains(&self.l2), "l2 must be in [0, 1)");
+    }
+}
+
+/// What to do with a weight that an update pushed to the wrong side of zero, i.e. an
+/// excitatory weight that became negative or an inhibitory one that became positive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SignConstraint {
+    /// Leave it, the violations are only counted.
+    #[default]
+    Unconstrained,
+    /// Set it to zero.
+    Clamp,
+    /// Mirror it back to its own sign.
+    Reflect,
+    /// Draw a new initial weight of its sign with the layer's `Initializer`, from a generator
+    /// seeded with the value. Every sub-network and every layer of a stack draws from its own
+    /// stream of that seed.
+    Resample(u64),
+}
+
+impl SignConstraint {
+    // The same policy for layer `index` of a stack, resampling from a stream of its own.
+    pub(super) fn for_layer(self, index: usize) -> Self {
+        match self {
+            SignConstraint::Resample(seed) => {
+                SignConstraint::Resample(mix_seed(seed, index as u64))
+            }
+            _ => self,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 enum NeuronType {
     Excitatory,
     Inhibitory,
 }
 
 impl NeuronType {
-    fn new(i: usize, j: usize) -> Self {
-        if i % 2 == j % 2 {
+    fn new(excitatory: bool) -> Self {
+        if excitatory {
             NeuronType::Excitatory
         } else {
             NeuronType::Inhibitory
         }
     }
 
-    fn weight<R>(&self, rng: &mut R) -> f64
+    // The type of the synapse from an `input` neuron to this one.
+    fn synapse(self, input: NeuronType) -> Self {
+        NeuronType::new(self == input)
+    }
+
+    // Index of the amine that trains the synapses from this neuron.
+    fn amine(self) -> usize {
+        match self {
+            NeuronType::Excitatory => 0,
+            NeuronType::Inhibitory => 1,
+        }
+    }
+
+    fn weight<R, F>(&self, rng: &mut R, initializer: &Initializer, input: usize, output: usize) -> F
     where
         R: Rng,
+        F: Float,
     {
+        self.sign::<F>() * F::cast(initializer.magnitude(rng, input, output))
+    }
+
+    fn sign<F: Float>(&self) -> F {
         match self {
-            NeuronType::Excitatory => rng.gen(),
-            NeuronType::Inhibitory => -rng.gen::<f64>(),
+            NeuronType::Excitatory => F::one(),
+            NeuronType::Inhibitory => -F::one(),
         }
     }
 }
 
-#[derive(Debug)]
-struct Neuron {
-    neuron_type: NeuronType,
-    weight: f64,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchReduction {
+    Mean,
+    Sum,
 }
 
-impl Neuron {
-    fn new<R>(rng: &mut R, i: usize, j: usize) -> Self
-    where
-        R: Rng,
-    {
-        let neuron_type = NeuronType::new(i, j);
-        Neuron {
-            weight: neuron_type.weight(rng),
-            neuron_type,
+impl BatchReduction {
+    fn scale(&self, batch_size: usize) -> f64 {
+        match self {
+            BatchReduction::Mean => 1. / batch_size as f64,
+            BatchReduction::Sum => 1.,
         }
     }
+}
+
+/// Error of one output as the two amines of ED, both non-negative. `positive` raises the
+/// output through the synapses from excitatory inputs, `negative` lowers it through the ones
+/// from inhibitory inputs, like `del_ot` in `src/ed.rs`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Amines<F = f64> {
+    pub positive: F,
+    pub negative: F,
+}
+
+impl<F: Float> Amines<F> {
+    pub fn new(positive: F, negative: F) -> Self {
+        Amines { positive, negative }
+    }
 
-    fn forward(&self, input: f64) -> f64 {
-        input * self.weight
+    /// The equivalent signed `output - target` delta.
+    pub fn delta(&self) -> F {
+        self.negative - self.positive
     }
+}
+
+impl<F: Float> Mul<F> for Amines<F> {
+    type Output = Amines<F>;
 
-    fn append_weight(&mut self, delta: f64) {
-        match self.neuron_type {
-            NeuronType::Excitatory => self.weight -= delta,
-            NeuronType::Inhibitory => self.weight += delta,
+    fn mul(self, rate: F) -> Amines<F> {
+        Amines::new(self.positive * rate, self.negative * rate)
+    }
+}
+
+/// Splits a signed `output - target` delta, so only one amine is non-zero.
+impl<F: Float> From<F> for Amines<F> {
+    fn from(delta: F) -> Self {
+        if delta < F::zero() {
+            Amines::new(-delta, F::zero())
+        } else {
+            Amines::new(F::zero(), delta)
         }
     }
 }
 
-#[derive(Debug)]
-struct SingleOutputLayer<ActivationFunc>
-where
-    ActivationFunc: DifferentiableFn<Args = f64>,
-{
-    neurons: Vec<Neuron>,
-    last_output: f64,
-    _f: PhantomData<ActivationFunc>,
+// The bias pairs are indexed by amine, `[positive, negative]`: the first input is excitatory and
+// trained by the positive amine, the second inhibitory and trained by the negative amine. Whether
+// a weight is positive depends on the type of its output.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Bias<F> {
+    beta: F,
+    signs: Vec<[F; 2]>,
 }
 
-impl<ActivationFunc> SingleOutputLayer<ActivationFunc>
-where
-    ActivationFunc: DifferentiableFn<Args = f64>,
-{
-    fn new<R>(rng: &mut R, index: usize, input: usize) -> Self
+// Weights are stored column-major (`weights[j * output + k]` connects input `j` to output `k`),
+// so a forward pass adds `inputs[j] * column` to all outputs at once. Every output still sums
+// its terms in input order. `signs` is +1 for excitatory and -1 for inhibitory synapses.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(bound(deserialize = "F: Float"))]
+struct WeightMatrix<F> {
+    input: usize,
+    output: usize,
+    weights: Vec<F>,
+    signs: Vec<F>,
+    input_types: Vec<NeuronType>,
+    bias: Option<Bias<F>>,
+    bias_weights: Vec<[F; 2]>,
+    #[serde(skip)]
+    last_outputs: Vec<Vec<F>>,
+    #[serde(skip)]
+    sign_constraint: SignConstraint,
+    #[serde(skip)]
+    sign_violations: usize,
+    #[serde(skip)]
+    weight_decay: WeightDecay,
+    #[serde(skip)]
+    rng: Option<StdRng>,
+    // Draws the weights of `SignConstraint::Resample`, the default one after `from_state`.
+    #[serde(skip)]
+    initializer: Initializer,
+    #[serde(skip)]
+    noise: Noise,
+    #[serde(skip)]
+    noise_rng: Option<StdRng>,
+    // The kept outputs of every sample of the last forward pass, empty without output dropout.
+    #[serde(skip)]
+    last_kept: Vec<Vec<bool>>,
+}
+
+impl<F: Float> WeightMatrix<F> {
+    fn new<R>(rng: &mut R, input: usize, output: usize, config: &LayerConfig) -> Self
     where
         R: Rng,
     {
-        let neurons: Vec<_> = (0..input).map(|j| Neuron::new(rng, index, j)).collect();
-        SingleOutputLayer {
-            neurons,
-            last_output: 0.,
-            _f: PhantomData,
+        config.weight_decay.validate();
+        config.noise.validate();
+        config.initializer.validate();
+        config.layout.validate();
+        let mut weights = vec![F::zero(); input * output];
+        let mut signs = vec![F::zero(); input * output];
+        let mut bias = config.bias.map(|beta| Bias {
+            beta: F::cast(beta),
+            signs: Vec::with_capacity(output),
+        });
+        let mut bias_weights = Vec::new();
+        let input_types = config.layout.types(input);
+        let output_types = config.layout.types(output);
+
+        for (i, output_type) in output_types.into_iter().enumerate() {
+            for (j, &input_type) in input_types.iter().enumerate() {
+                let neuron_type = output_type.synapse(input_type);
+                weights[j * output + i] =
+                    neuron_type.weight(rng, &config.initializer, input, output);
+                signs[j * output + i] = neuron_type.sign();
+            }
+            if let Some(bias) = &mut bias {
+                // The bias pair is an excitatory and an inhibitory input, in amine order.
+                let types = [
+                    output_type.synapse(NeuronType::Excitatory),
+                    output_type.synapse(NeuronType::Inhibitory),
+                ];
+                let weight = |neuron_type: &NeuronType, rng: &mut R| -> F {
+                    neuron_type.weight(rng, &config.initializer, input, output)
+                };
+                bias_weights.push([weight(&types[0], rng), weight(&types[1], rng)]);
+                bias.signs.push([types[0].sign(), types[1].sign()]);
+            }
+        }
+
+        let mut matrix = WeightMatrix {
+            input,
+            output,
+            weights,
+            signs,
+            input_types,
+            bias,
+            bias_weights,
+            last_outputs: Vec::new(),
+            sign_constraint: SignConstraint::default(),
+            sign_violations: 0,
+            weight_decay: config.weight_decay,
+            rng: None,
+            initializer: config.initializer,
+            noise: config.noise,
+            noise_rng: (!config.noise.is_zero()).then(|| StdRng::seed_from_u64(rng.gen())),
+            last_kept: Vec::new(),
+        };
+        matrix.set_sign_constraint(config.sign_constraint, 0);
+        matrix
+    }
+
+    fn set_noise(&mut self, noise: Noise, seed: u64) {
+        noise.validate();
+        self.noise = noise;
+        self.noise_rng = Some(StdRng::seed_from_u64(seed));
+    }
+
+    // Adds the input noise and drops inputs, before the inputs are kept for `backward`.
+    fn perturb_inputs(&mut self, inputs: &mut [F]) {
+        let Noise {
+            input_dropout,
+            input_std,
+            ..
+        } = self.noise;
+        if input_dropout == 0. && input_std == 0. {
+            return;
+        }
+        let rng = self.noise_rng.as_mut().unwrap();
+        if input_std > 0. {
+            for x in inputs.iter_mut() {
+                let noisy = *x + F::cast(input_std * rng.sample::<f64, _>(StandardNormal));
+                // A negative input would flip the signs of its ED steps, so the noise stops at 0.
+                *x = if noisy < F::zero() && *x >= F::zero() {
+                    F::zero()
+                } else {
+                    noisy
+                };
+            }
+        }
+        if input_dropout > 0. {
+            drop_out(rng, input_dropout, inputs);
         }
     }
 
-    fn forward_without_activation(&self, inputs: &Vec<f64>) -> f64 {
-        self.neurons
+    fn drop_outputs(&mut self, outputs: &mut [Vec<F>]) {
+        let rate = self.noise.output_dropout;
+        self.last_kept = if rate == 0. {
+            Vec::new()
+        } else {
+            let rng = self.noise_rng.as_mut().unwrap();
+            outputs
+                .iter_mut()
+                .map(|output| drop_out(rng, rate, output))
+                .collect()
+        };
+    }
+
+    // Dropped outputs release no amines, so their synapses are left untouched.
+    fn skip_dropped(&self, sample: usize, deltas: &mut [F], amines: &mut [Vec<F>; 2]) {
+        let Some(kept) = self.last_kept.get(sample) else {
+            return;
+        };
+        for (k, _) in kept.iter().enumerate().filter(|(_, &kept)| !kept) {
+            deltas[k] = F::zero();
+            amines[0][k] = F::zero();
+            amines[1][k] = F::zero();
+        }
+    }
+
+    fn decay_weights(&mut self) {
+        if self.weight_decay.is_zero() {
+            return;
+        }
+        let WeightDecay { l1, l2 } = self.weight_decay;
+        let (keep, l1) = (F::cast(1. - l2), F::cast(l1));
+        for w in self.weights.iter_mut() {
+            let magnitude = (w.abs() * keep - l1).max(F::zero());
+            *w = magnitude.copysign(*w);
+        }
+    }
+
+    // `stream` gives the sub-networks of a `MultiOutputLayer` their own resampling generators.
+    fn set_sign_constraint(&mut self, sign_constraint: SignConstraint, stream: u64) {
+        self.sign_constraint = sign_constraint;
+        self.rng = match sign_constraint {
+            SignConstraint::Resample(seed) => Some(StdRng::seed_from_u64(mix_seed(seed, stream))),
+            _ => None,
+        };
+    }
+
+    // Counts the weights on the wrong side of zero after an update and applies the policy.
+    // With `last_inputs`, only the columns of non-zero inputs changed and are checked.
+    fn constrain_signs(&mut self, last_inputs: Option<&[F]>) {
+        let sign_constraint = self.sign_constraint;
+        let (initializer, input, output) = (self.initializer, self.input, self.output);
+        let rng = &mut self.rng;
+        let mut violations = 0;
+        let mut constrain = |w: &mut F, sign: F| {
+            if *w * sign >= F::zero() {
+                return;
+            }
+            violations += 1;
+            match sign_constraint {
+                SignConstraint::Unconstrained => {}
+                SignConstraint::Clamp => *w = F::zero(),
+                SignConstraint::Reflect => *w = -*w,
+                SignConstraint::Resample(_) => {
+                    let rng = rng
+                        .as_mut()
+                        .expect("set_sign_constraint seeds the generator");
+                    *w = sign * F::cast(initializer.magnitude(rng, input, output));
+                }
+            }
+        };
+
+        let columns = self
+            .weights
+            .chunks_exact_mut(self.output)
+            .zip(self.signs.chunks_exact(self.output));
+        for (j, (weights, signs)) in columns.enumerate() {
+            if last_inputs.is_some_and(|inputs| inputs[j] == F::zero()) {
+                continue;
+            }
+            for (w, &sign) in weights.iter_mut().zip(signs) {
+                constrain(w, sign);
+            }
+        }
+        if let Some(bias) = &self.bias {
+            let weights = self.bias_weights.iter_mut().flatten();
+            for (w, &sign) in weights.zip(bias.signs.iter().flatten()) {
+                constrain(w, sign);
+            }
+        }
+        self.sign_violations += violations;
+    }
+
+    fn validate(&self) -> Result<(), ModelError> {
+        if self.input_types.len() != self.input {
+            return Err(ModelError::ArchitectureMismatch(format!(
+                "{} neuron types for {} inputs",
+                self.input_types.len(),
+                self.input
+            )));
+        }
+        let size = self.input * self.output;
+        if self.weights.len() != size || self.signs.len() != size {
+            return Err(ModelError::ArchitectureMismatch(format!(
+                "{} weights and {} signs for {} inputs and {} outputs",
+                self.weights.len(),
+                self.signs.len(),
+                self.input,
+                self.output
+            )));
+        }
+        let bias_size = self.bias.as_ref().map_or(0, |bias| bias.signs.len());
+        if self.bias.is_some() && bias_size != self.output || self.bias_weights.len() != bias_size {
+            return Err(ModelError::ArchitectureMismatch(format!(
+                "{} bias weights and {} bias signs for {} outputs",
+                self.bias_weights.len(),
+                bias_size,
+                self.output
+            )));
+        }
+        let signs = self.signs.iter();
+        let bias_signs = self
+            .bias
             .iter()
-            .zip(inputs.iter())
-            .map(|(neuron, input)| neuron.forward(*input))
-            .sum()
+            .flat_map(|bias| bias.signs.iter().flatten());
+        if signs
+            .chain(bias_signs)
+            .any(|&sign| sign != F::one() && sign != -F::one())
+        {
+            return Err(ModelError::ArchitectureMismatch(
+                "neuron signs must be 1 or -1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn forward_without_activation(&self, inputs: &[F]) -> Vec<F> {
+        assert_eq!(inputs.len(), self.input);
+        let mut outputs = vec![F::zero(); self.output];
+        for (column, &x) in self.weights.chunks_exact(self.output).zip(inputs.iter()) {
+            if x == F::zero() {
+                continue;
+            }
+            for (output, w) in outputs.iter_mut().zip(column.iter()) {
+                *output += x * *w;
+            }
+        }
+        if let Some(bias) = &self.bias {
+            for (output, weights) in outputs.iter_mut().zip(self.bias_weights.iter()) {
+                *output += bias.beta * weights[0] + bias.beta * weights[1];
+            }
+        }
+        outputs
+    }
+
+    fn forward<A>(&mut self, activation: &A, inputs: &[F]) -> Vec<F>
+    where
+        A: DifferentiableFn<F, Args = F>,
+    {
+        let last_output = self.forward_without_activation(inputs);
+        let mut output = vec![last_output.iter().map(|&u| activation.eval(u)).collect()];
+        self.last_outputs = vec![last_output];
+        self.drop_outputs(&mut output);
+        output.pop().unwrap()
     }
 
-    fn forward(&mut self, inputs: &Vec<f64>) -> f64 {
-        self.last_output = self.forward_without_activation(inputs);
-        ActivationFunc::eval(self.last_output)
+    fn forward_batch<A>(&mut self, activation: &A, inputs: &[&[F]]) -> Vec<Vec<F>>
+    where
+        A: DifferentiableFn<F, Args = F>,
+    {
+        self.last_outputs = inputs
+            .iter()
+            .map(|inputs| self.forward_without_activation(inputs))
+            .collect();
+        let mut outputs: Vec<_> = self
+            .last_outputs
+            .iter()
+            .map(|last_output| last_output.iter().map(|&u| activation.eval(u)).collect())
+            .collect();
+        self.drop_outputs(&mut outputs);
+        outputs
+    }
+
+    fn forward_without_train<A>(&self, activation: &A, inputs: &[F]) -> Vec<F>
+    where
+        A: DifferentiableFn<F, Args = F>,
+    {
+        self.forward_without_activation(inputs)
+            .into_iter()
+            .map(|u| activation.eval(u))
+            .collect()
+    }
+
+    // Returns the deltas of every output and `amines[amine][k]`, the step of output `k` for
+    // the inputs trained by that amine. The positive amine trains the synapses from excitatory
+    // inputs, the negative amine the ones from inhibitory inputs.
+    fn amines<A>(
+        &self,
+        activation: &A,
+        error: Amines<F>,
+        last_output: &[F],
+    ) -> (Vec<F>, [Vec<F>; 2])
+    where
+        A: DifferentiableFn<F, Args = F>,
+    {
+        let delta = error.delta();
+        let mut deltas = Vec::with_capacity(self.output);
+        let mut amines = [vec![F::zero(); self.output], vec![F::zero(); self.output]];
+        for (k, &u) in last_output.iter().enumerate() {
+            let derivative = activation.derivative(u);
+            deltas.push(derivative * delta);
+            amines[0][k] = -(derivative * error.positive);
+            amines[1][k] = -(derivative * error.negative);
+        }
+
+        (deltas, amines)
+    }
+
+    // Subtracts the ED step of one sample from `weights` and `bias_weights`, which are either
+    // the weights themselves or an accumulator of the same shape.
+    fn descend(
+        &self,
+        amines: &[Vec<F>; 2],
+        last_inputs: &[F],
+        weights: &mut [F],
+        bias_weights: &mut [[F; 2]],
+    ) {
+        for (j, &x) in last_inputs.iter().enumerate().take(self.input) {
+            if x == F::zero() {
+                continue;
+            }
+            let column = j * self.output..(j + 1) * self.output;
+            let weights = &mut weights[column.clone()];
+            let signs = &self.signs[column];
+            let amines = &amines[self.input_types[j].amine()];
+            for ((w, s), amine) in weights.iter_mut().zip(signs).zip(amines) {
+                *w -= *s * (*amine * x);
+            }
+        }
+
+        if let Some(bias) = &self.bias {
+            for (k, (weights, signs)) in bias_weights.iter_mut().zip(bias.signs.iter()).enumerate()
+            {
+                for amine in 0..2 {
+                    weights[amine] -= signs[amine] * (amines[amine][k] * bias.beta);
+                }
+            }
+        }
+    }
+
+    // Compares the ED changes of one sample with the negative backprop gradients, given the
+    // gradient of the loss with respect to the outputs. Returns the gradient with respect to
+    // the inputs for the layer below.
+    fn gradient_alignment<A>(
+        &self,
+        activation: &A,
+        error: Amines<F>,
+        inputs: &[F],
+        output_gradient: &[F],
+    ) -> (LayerAlignment, Vec<F>)
+    where
+        A: DifferentiableFn<F, Args = F>,
+    {
+        assert_eq!(output_gradient.len(), self.output);
+        let last_output = self.forward_without_activation(inputs);
+        let (_, amines) = self.amines(activation, error, &last_output);
+        let mut ed = vec![F::zero(); self.weights.len()];
+        let mut ed_bias = vec![[F::zero(); 2]; self.bias_weights.len()];
+        self.descend(&amines, inputs, &mut ed, &mut ed_bias);
+
+        let deltas: Vec<_> = last_output
+            .iter()
+            .zip(output_gradient)
+            .map(|(&u, gradient)| activation.derivative(u) * *gradient)
+            .collect();
+        let mut bp = vec![F::zero(); self.weights.len()];
+        let mut input_gradient = vec![F::zero(); self.input];
+        for (j, &x) in inputs.iter().enumerate() {
+            let column = j * self.output..(j + 1) * self.output;
+            for ((bp, w), delta) in bp[column.clone()]
+                .iter_mut()
+                .zip(&self.weights[column])
+                .zip(&deltas)
+            {
+                *bp = -*delta * x;
+                input_gradient[j] += *w * *delta;
+            }
+        }
+        let bp_bias: Vec<_> = match &self.bias {
+            Some(bias) => deltas
+                .iter()
+                .map(|delta| [-*delta * bias.beta; 2])
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let neurons = (0..self.output)
+            .map(|k| {
+                let neuron = |weights: &[F], bias_weights: &[[F; 2]]| {
+                    let mut neuron: Vec<_> = weights
+                        .iter()
+                        .skip(k)
+                        .step_by(self.output)
+                        .copied()
+                        .collect();
+                    neuron.extend(bias_weights.get(k).into_iter().flatten());
+                    neuron
+                };
+                Alignment::new(
+                    &as_f64(&neuron(&ed, &ed_bias)),
+                    &as_f64(&neuron(&bp, &bp_bias)),
+                )
+            })
+            .collect();
+        ed.extend(ed_bias.iter().flatten());
+        bp.extend(bp_bias.iter().flatten());
+        let alignment = LayerAlignment {
+            layer: Alignment::new(&as_f64(&ed), &as_f64(&bp)),
+            neurons,
+        };
+
+        (alignment, input_gradient)
+    }
+
+    // Adds the accumulated steps after `optimizer` turned them into weight changes.
+    fn apply(
+        &mut self,
+        optimizer: &mut dyn Optimizer<F>,
+        learning_rate: f64,
+        steps: Vec<F>,
+        bias_steps: Vec<[F; 2]>,
+    ) {
+        let mut steps = steps;
+        steps.extend(bias_steps.iter().flatten());
+        optimizer.update(&mut steps, learning_rate);
+
+        let (steps, bias_steps) = steps.split_at(self.weights.len());
+        for (w, step) in self.weights.iter_mut().zip(steps) {
+            *w += *step;
+        }
+        for (w, step) in self.bias_weights.iter_mut().flatten().zip(bias_steps) {
+            *w += *step;
+        }
     }
 
-    fn forward_without_train(&self, inputs: &Vec<f64>) -> f64 {
-        ActivationFunc::eval(self.forward_without_activation(inputs))
+    // Without an optimizer, the steps are subtracted from the weights in place.
+    fn backward<A>(
+        &mut self,
+        activation: &A,
+        error: Amines<F>,
+        last_inputs: &[F],
+        optimizer: &mut Option<Box<dyn Optimizer<F>>>,
+        learning_rate: f64,
+    ) -> Vec<F>
+    where
+        A: DifferentiableFn<F, Args = F>,
+    {
+        assert_eq!(
+            self.last_outputs.len(),
+            1,
+            "backward needs a single sample, use backward_batch after forward_batch"
+        );
+        let (mut deltas, mut amines) = self.amines(activation, error, &self.last_outputs[0]);
+        self.skip_dropped(0, &mut deltas, &mut amines);
+
+        match optimizer {
+            None => {
+                let mut weights = std::mem::take(&mut self.weights);
+                let mut bias_weights = std::mem::take(&mut self.bias_weights);
+                self.descend(&amines, last_inputs, &mut weights, &mut bias_weights);
+                self.weights = weights;
+                self.bias_weights = bias_weights;
+                self.decay_weights();
+                self.constrain_signs(Some(last_inputs));
+            }
+            Some(optimizer) => {
+                let mut steps = vec![F::zero(); self.weights.len()];
+                let mut bias_steps = vec![[F::zero(); 2]; self.bias_weights.len()];
+                self.descend(&amines, last_inputs, &mut steps, &mut bias_steps);
+                self.apply(optimizer.as_mut(), learning_rate, steps, bias_steps);
+                self.decay_weights();
+                self.constrain_signs(None);
+            }
+        }
+
+        deltas
     }
 
-    fn backward(&mut self, delta: f64, last_inputs: &Vec<f64>) -> f64 {
-        let delta = ActivationFunc::derivative(self.last_output) * delta;
+    fn backward_batch<A>(
+        &mut self,
+        activation: &A,
+        errors: &[Amines<F>],
+        last_inputs: &[&[F]],
+        reduction: BatchReduction,
+        optimizer: &mut Option<Box<dyn Optimizer<F>>>,
+        learning_rate: f64,
+    ) -> Vec<Vec<F>>
+    where
+        A: DifferentiableFn<F, Args = F>,
+    {
+        assert_eq!(self.last_outputs.len(), errors.len());
+        // The mean of an empty batch would turn every weight into NaN.
+        assert!(
+            !errors.is_empty(),
+            "backward_batch needs at least one sample"
+        );
+        let mut steps = vec![F::zero(); self.weights.len()];
+        let mut bias_steps = vec![[F::zero(); 2]; self.bias_weights.len()];
+
+        let deltas = errors
+            .iter()
+            .zip(self.last_outputs.iter())
+            .zip(last_inputs.iter())
+            .enumerate()
+            .map(|(sample, ((&error, last_output), last_inputs))| {
+                let (mut deltas, mut amines) = self.amines(activation, error, last_output);
+                self.skip_dropped(sample, &mut deltas, &mut amines);
+                self.descend(&amines, last_inputs, &mut steps, &mut bias_steps);
+                deltas
+            })
+            .collect();
 
-        for (i, neuron) in self.neurons.iter_mut().enumerate() {
-            if delta < 0. {
-                if i % 2 == 0 {
-                    neuron.append_weight(delta * last_inputs[i]);
+        let scale = F::cast(reduction.scale(self.last_outputs.len()));
+        match optimizer {
+            None => {
+                for (w, step) in self.weights.iter_mut().zip(steps) {
+                    *w += step * scale;
                 }
-            } else {
-                if i % 2 == 1 {
-                    neuron.append_weight(-delta * last_inputs[i]);
+                for (weights, steps) in self.bias_weights.iter_mut().zip(bias_steps) {
+                    for amine in 0..2 {
+                        weights[amine] += steps[amine] * scale;
+                    }
                 }
             }
+            Some(optimizer) => {
+                let steps = steps.into_iter().map(|step| step * scale).collect();
+                let bias_steps = bias_steps
+                    .into_iter()
+                    .map(|steps| steps.map(|step| step * scale))
+                    .collect();
+                self.apply(optimizer.as_mut(), learning_rate, steps, bias_steps);
+            }
         }
+        self.decay_weights();
+        self.constrain_signs(None);
 
-        delta
+        deltas
     }
 }
 
-pub struct MultiOutputLayer<ActivationFunc>
+fn as_f64<F: Float>(values: &[F]) -> Vec<f64> {
+    values.iter().map(|value| value.as_f64()).collect()
+}
+
+// Maps `f` over the sub-networks, optionally on rayon's global thread pool, whose threads are
+// started once and reused by every call. The results keep the order of `layers` either way.
+fn map_layers<I, T, F>(layers: Vec<I>, parallel: bool, f: F) -> Vec<T>
 where
-    ActivationFunc: DifferentiableFn<Args = f64>,
+    I: Send,
+    T: Send,
+    F: Fn(usize, I) -> T + Sync,
 {
-    inner_layers: Vec<Vec<SingleOutputLayer<ActivationFunc>>>,
-    last_inputs: Vec<Vec<f64>>,
+    if !parallel {
+        return layers
+            .into_iter()
+            .enumerate()
+            .map(|(i, layer)| f(i, layer))
+            .collect();
+    }
+
+    layers
+        .into_par_iter()
+        .enumerate()
+        .map(|(i, layer)| f(i, layer))
+        .collect()
+}
+
+pub struct MultiOutputLayer<ActivationFunc, F = f64>
+where
+    ActivationFunc: DifferentiableFn<F, Args = F>,
+    F: Float,
+{
+    inner_layers: Vec<WeightMatrix<F>>,
+    activation: ActivationFunc,
+    last_inputs: Vec<Vec<Vec<F>>>,
+    parallel: bool,
+    optimizers: Vec<Option<Box<dyn Optimizer<F>>>>,
+    schedule: ModelSchedule,
 }
 
-impl<ActivationFunc> MultiOutputLayer<ActivationFunc>
+impl<ActivationFunc, F> MultiOutputLayer<ActivationFunc, F>
 where
-    ActivationFunc: DifferentiableFn<Args = f64>,
+    ActivationFunc: DifferentiableFn<F, Args = F>,
+    F: Float,
 {
     pub fn new<R>(rng: &mut R, last_output: usize, input: usize, output: usize) -> Self
     where
         R: Rng,
+        ActivationFunc: Default,
     {
-        MultiOutputLayer {
-            inner_layers: (0..last_output)
-                .map(|_| {
-                    (0..output)
-                        .map(|i| SingleOutputLayer::new(rng, i, input))
-                        .collect()
-                })
+        Self::with_config(
+            rng,
+            last_output,
+            input,
+            output,
+            ActivationFunc::default(),
+            LayerConfig::default(),
+        )
+    }
+
+    pub fn with_config<R>(
+        rng: &mut R,
+        last_output: usize,
+        input: usize,
+        output: usize,
+        activation: ActivationFunc,
+        config: LayerConfig,
+    ) -> Self
+    where
+        R: Rng,
+    {
+        Self::with_inputs(rng, &vec![input; last_output], output, activation, config)
+    }
+
+    /// Sub-network `i` takes `inputs[i]` inputs, e.g. to give every output its own features.
+    pub fn with_inputs<R>(
+        rng: &mut R,
+        inputs: &[usize],
+        output: usize,
+        activation: ActivationFunc,
+        config: LayerConfig,
+    ) -> Self
+    where
+        R: Rng,
+    {
+        let mut layer = MultiOutputLayer {
+            inner_layers: inputs
+                .iter()
+                .map(|&input| WeightMatrix::new(rng, input, output, &config))
                 .collect(),
+            activation,
             last_inputs: Vec::new(),
-        }
+            parallel: false,
+            optimizers: inputs.iter().map(|_| None).collect(),
+            schedule: ModelSchedule::default(),
+        };
+        layer.set_sign_constraint(config.sign_constraint);
+        layer
+    }
+
+    /// Runs the independent sub-networks on rayon's thread pool. The results are the same as the
+    /// sequential ones; it pays off once the work per call is large, e.g. with `forward_batch`
+    /// and `backward_batch`. Off by default, measure with `cargo bench` before enabling it.
+    pub fn set_parallel(&mut self, parallel: bool) {
+        self.parallel = parallel;
+    }
+
+    /// Gives every sub-network its own copy of `optimizer`.
+    pub fn set_optimizer<O>(&mut self, optimizer: O)
+    where
+        O: Optimizer<F> + Clone + 'static,
+    {
+        self.set_optimizer_with(&|| Box::new(optimizer.clone()));
     }
 
-    pub fn forward(&mut self, inputs: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
-        let output = self
+    pub(super) fn set_optimizer_with(&mut self, new_optimizer: &dyn Fn() -> Box<dyn Optimizer<F>>) {
+        self.optimizers = self
             .inner_layers
-            .iter_mut()
-            .zip(inputs.iter())
-            .map(|(layers, inputs)| {
-                layers
-                    .iter_mut()
-                    .map(|layer| layer.forward(&inputs))
-                    .collect()
-            })
+            .iter()
+            .map(|_| Some(new_optimizer()))
             .collect();
-        self.last_inputs = inputs;
+    }
 
-        output
+    /// Replaces the policy of `LayerConfig`.
+    pub fn set_sign_constraint(&mut self, sign_constraint: SignConstraint) {
+        for (i, layer) in self.inner_layers.iter_mut().enumerate() {
+            layer.set_sign_constraint(sign_constraint, i as u64);
+        }
     }
 
-    pub fn forward_without_train(&self, inputs: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+    /// Weights found on the wrong side of zero after an update, summed over all updates and
+    /// sub-networks. Under a constraint every violation was corrected.
+    pub fn sign_violations(&self) -> usize {
         self.inner_layers
             .iter()
-            .zip(inputs.iter())
-            .map(|(layers, inputs)| {
-                layers
+            .map(|layer| layer.sign_violations)
+            .sum()
+    }
+
+    /// Replaces the decay of `LayerConfig`.
+    pub fn set_weight_decay(&mut self, weight_decay: WeightDecay) {
+        weight_decay.validate();
+        for layer in self.inner_layers.iter_mut() {
+            layer.weight_decay = weight_decay;
+        }
+    }
+
+    /// Replaces the noise of `LayerConfig`, seeding every sub-network from `seed`.
+    pub fn set_noise(&mut self, noise: Noise, seed: u64) {
+        for (i, layer) in self.inner_layers.iter_mut().enumerate() {
+            layer.set_noise(noise, mix_seed(seed, i as u64));
+        }
+    }
+
+    /// The errors passed to `backward` are scaled by the rate of `schedule`, which the caller
+    /// advances with `step_schedule`.
+    pub fn set_schedule<S>(&mut self, schedule: S)
+    where
+        S: Schedule + 'static,
+    {
+        self.schedule.set(schedule);
+    }
+
+    pub fn learning_rate(&self) -> Option<f64> {
+        self.schedule.learning_rate()
+    }
+
+    pub fn step_schedule(&mut self, metric: Option<f64>) {
+        self.schedule.step(metric);
+    }
+
+    pub fn last_output(&self) -> usize {
+        self.inner_layers.len()
+    }
+
+    pub fn inputs(&self) -> Vec<usize> {
+        self.inner_layers.iter().map(|layer| layer.input).collect()
+    }
+
+    pub fn outputs(&self) -> Vec<usize> {
+        self.inner_layers.iter().map(|layer| layer.output).collect()
+    }
+
+    pub fn forward(&mut self, inputs: Vec<Vec<F>>) -> Vec<Vec<F>> {
+        assert_eq!(inputs.len(), self.inner_layers.len());
+        let mut inputs = inputs;
+        for (layer, inputs) in self.inner_layers.iter_mut().zip(inputs.iter_mut()) {
+            layer.perturb_inputs(inputs);
+        }
+        let activation = &self.activation;
+        let output = map_layers(
+            self.inner_layers.iter_mut().zip(inputs.iter()).collect(),
+            self.parallel,
+            |_, (layer, inputs)| layer.forward(activation, inputs),
+        );
+        self.last_inputs = vec![inputs];
+
+        output
+    }
+
+    /// Runs a mini-batch, `inputs[sample][output]`, and keeps the state of every sample for
+    /// `backward_batch`.
+    pub fn forward_batch(&mut self, inputs: Vec<Vec<Vec<F>>>) -> Vec<Vec<Vec<F>>> {
+        let mut inputs = inputs;
+        for inputs in inputs.iter_mut() {
+            assert_eq!(inputs.len(), self.inner_layers.len());
+            for (layer, inputs) in self.inner_layers.iter_mut().zip(inputs.iter_mut()) {
+                layer.perturb_inputs(inputs);
+            }
+        }
+        let activation = &self.activation;
+        let outputs = map_layers(
+            self.inner_layers.iter_mut().collect(),
+            self.parallel,
+            |i, layer| {
+                let inputs: Vec<_> = inputs.iter().map(|inputs| &inputs[i][..]).collect();
+                layer.forward_batch(activation, &inputs)
+            },
+        );
+        self.last_inputs = inputs;
+
+        (0..self.last_inputs.len())
+            .map(|sample| {
+                outputs
                     .iter()
-                    .map(|layer| layer.forward_without_train(&inputs))
+                    .map(|outputs| outputs[sample].clone())
                     .collect()
             })
             .collect()
     }
 
-    pub fn backward(&mut self, deltas: &Vec<f64>) {
-        for (layers, delta) in self.inner_layers.iter_mut().zip(deltas.iter()) {
-            for layer in layers {
-                layer.backward(*delta, &self.last_inputs[0]);
-            }
-        }
+    pub fn forward_without_train(&self, inputs: Vec<Vec<F>>) -> Vec<Vec<F>> {
+        assert_eq!(inputs.len(), self.inner_layers.len());
+        map_layers(
+            self.inner_layers.iter().zip(inputs.iter()).collect(),
+            self.parallel,
+            |_, (layer, inputs)| layer.forward_without_train(&self.activation, inputs),
+        )
+    }
+
+    /// `Layer::gradient_alignment` of every sub-network, which takes `inputs[i]`, `errors[i]` and
+    /// `output_gradients[i]`.
+    pub fn gradient_alignment<E>(
+        &self,
+        inputs: &[Vec<F>],
+        errors: &[E],
+        output_gradients: &[Vec<F>],
+    ) -> (Vec<LayerAlignment>, Vec<Vec<F>>)
+    where
+        E: Into<Amines<F>> + Copy,
+    {
+        assert_eq!(inputs.len(), self.inner_layers.len());
+        assert_eq!(errors.len(), self.inner_layers.len());
+        self.inner_layers
+            .iter()
+            .zip(inputs)
+            .zip(errors)
+            .zip(output_gradients)
+            .map(|(((layer, inputs), &error), output_gradient)| {
+                layer.gradient_alignment(&self.activation, error.into(), inputs, output_gradient)
+            })
+            .unzip()
+    }
+
+    /// Takes one error per output, either a signed delta or `Amines`.
+    pub fn backward<E>(&mut self, errors: &[E])
+    where
+        E: Into<Amines<F>> + Copy + Sync,
+    {
+        self.backward_with_rate(errors, 1.);
+    }
+
+    /// `backward` with the errors scaled by `learning_rate`, on top of the layer's own schedule.
+    pub fn backward_with_rate<E>(&mut self, errors: &[E], learning_rate: f64)
+    where
+        E: Into<Amines<F>> + Copy + Sync,
+    {
+        let learning_rate = learning_rate * self.schedule.rate();
+        let activation = &self.activation;
+        let last_inputs = &self.last_inputs[0];
+        map_layers(
+            self.inner_layers
+                .iter_mut()
+                .zip(self.optimizers.iter_mut())
+                .zip(errors.iter())
+                .collect(),
+            self.parallel,
+            |i, ((layer, optimizer), &error)| {
+                let error = error.into() * F::cast(learning_rate);
+                layer.backward(activation, error, &last_inputs[i], optimizer, learning_rate)
+            },
+        );
+    }
+
+    /// Accumulates the ED steps of the last mini-batch, `errors[sample][output]`, and applies
+    /// them once. Panics on an empty batch.
+    pub fn backward_batch<E>(&mut self, errors: &[Vec<E>], reduction: BatchReduction)
+    where
+        E: Into<Amines<F>> + Copy + Sync,
+    {
+        self.backward_batch_with_rate(errors, reduction, 1.);
+    }
+
+    /// `backward_batch` with the errors scaled by `learning_rate`, see `backward_with_rate`.
+    pub fn backward_batch_with_rate<E>(
+        &mut self,
+        errors: &[Vec<E>],
+        reduction: BatchReduction,
+        learning_rate: f64,
+    ) where
+        E: Into<Amines<F>> + Copy + Sync,
+    {
+        let learning_rate = learning_rate * self.schedule.rate();
+        let activation = &self.activation;
+        let last_inputs = &self.last_inputs;
+        map_layers(
+            self.inner_layers
+                .iter_mut()
+                .zip(self.optimizers.iter_mut())
+                .collect(),
+            self.parallel,
+            |i, (layer, optimizer)| {
+                let last_inputs: Vec<_> = last_inputs.iter().map(|inputs| &inputs[i][..]).collect();
+                let errors: Vec<_> = errors
+                    .iter()
+                    .map(|errors| errors[i].into() * F::cast(learning_rate))
+                    .collect();
+                layer.backward_batch(
+                    activation,
+                    &errors,
+                    &last_inputs,
+                    reduction,
+                    optimizer,
+                    learning_rate,
+                )
+            },
+        );
     }
 }
 
 #[derive(Debug)]
-pub struct Layer<ActivationFunc>
+pub struct Layer<ActivationFunc, F = f64>
 where
-    ActivationFunc: DifferentiableFn<Args = f64>,
+    ActivationFunc: DifferentiableFn<F, Args = F>,
+    F: Float,
 {
-    inner_layers: Vec<SingleOutputLayer<ActivationFunc>>,
-    last_inputs: Vec<f64>,
+    weights: WeightMatrix<F>,
+    activation: ActivationFunc,
+    last_inputs: Vec<Vec<F>>,
+    optimizer: Option<Box<dyn Optimizer<F>>>,
 }
 
-impl<ActivationFunc> Layer<ActivationFunc>
+impl<ActivationFunc, F> Layer<ActivationFunc, F>
 where
-    ActivationFunc: DifferentiableFn<Args = f64>,
+    ActivationFunc: DifferentiableFn<F, Args = F>,
+    F: Float,
 {
     pub fn new<R>(rng: &mut R, input: usize, output: usize) -> Self
+    where
+        R: Rng,
+        ActivationFunc: Default,
+    {
+        Self::with_config(
+            rng,
+            input,
+            output,
+            ActivationFunc::default(),
+            LayerConfig::default(),
+        )
+    }
+
+    pub fn with_config<R>(
+        rng: &mut R,
+        input: usize,
+        output: usize,
+        activation: ActivationFunc,
+        config: LayerConfig,
+    ) -> Self
     where
         R: Rng,
     {
         Layer {
-            inner_layers: (0..output)
-                .map(|i| SingleOutputLayer::new(rng, i, input))
-                .collect(),
+            weights: WeightMatrix::new(rng, input, output, &config),
+            activation,
             last_inputs: Vec::new(),
+            optimizer: None,
         }
     }
 
-    pub fn forward(&mut self, inputs: Vec<f64>) -> Vec<f64> {
-        let output = self
-            .inner_layers
-            .iter_mut()
-            .map(|layer| layer.forward(&inputs))
-            .collect();
-        self.last_inputs = inputs;
+    /// Routes the weight updates through `optimizer`.
+    pub fn set_optimizer<O>(&mut self, optimizer: O)
+    where
+        O: Optimizer<F> + 'static,
+    {
+        self.optimizer = Some(Box::new(optimizer));
+    }
+
+    pub(super) fn set_optimizer_with(&mut self, new_optimizer: &dyn Fn() -> Box<dyn Optimizer<F>>) {
+        self.optimizer = Some(new_optimizer());
+    }
+
+    /// Replaces the policy of `LayerConfig`.
+    pub fn set_sign_constraint(&mut self, sign_constraint: SignConstraint) {
+        self.weights.set_sign_constraint(sign_constraint, 0);
+    }
+
+    /// Weights found on the wrong side of zero after an update, summed over all updates. Under
+    /// a constraint every violation was corrected.
+    pub fn sign_violations(&self) -> usize {
+        self.weights.sign_violations
+    }
+
+    /// Replaces the decay of `LayerConfig`.
+    pub fn set_weight_decay(&mut self, weight_decay: WeightDecay) {
+        weight_decay.validate();
+        self.weights.weight_decay = weight_decay;
+    }
+
+    /// Replaces the noise of `LayerConfig`.
+    pub fn set_noise(&mut self, noise: Noise, seed: u64) {
+        self.weights.set_noise(noise, seed);
+    }
+
+    pub fn input(&self) -> usize {
+        self.weights.input
+    }
+
+    // Whether the input neurons are typed by `layout`.
+    pub(super) fn has_layout(&self, layout: Layout) -> bool {
+        self.weights.input_types == layout.types(self.weights.input)
+    }
+
+    pub fn output(&self) -> usize {
+        self.weights.output
+    }
+
+    pub fn forward(&mut self, inputs: Vec<F>) -> Vec<F> {
+        let mut inputs = inputs;
+        self.weights.perturb_inputs(&mut inputs);
+        let output = self.weights.forward(&self.activation, &inputs);
+        self.last_inputs = vec![inputs];
 
         output
     }
 
-    pub fn forward_without_train(&self, inputs: Vec<f64>) -> Vec<f64> {
-        self.inner_layers
+    /// Runs a mini-batch and keeps the state of every sample for `backward_batch`.
+    pub fn forward_batch(&mut self, inputs: Vec<Vec<F>>) -> Vec<Vec<F>> {
+        let mut inputs = inputs;
+        for inputs in inputs.iter_mut() {
+            self.weights.perturb_inputs(inputs);
+        }
+        let batch: Vec<_> = inputs.iter().map(|inputs| &inputs[..]).collect();
+        let outputs = self.weights.forward_batch(&self.activation, &batch);
+        self.last_inputs = inputs;
+
+        outputs
+    }
+
+    pub fn forward_without_train(&self, inputs: Vec<F>) -> Vec<F> {
+        self.weights
+            .forward_without_train(&self.activation, &inputs)
+    }
+
+    /// Compares the update `backward` would make for `inputs` and `error` with plain gradient
+    /// descent, without changing the layer. `output_gradient` is the gradient of the loss with
+    /// respect to the outputs, the returned vector the one with respect to the inputs, to be
+    /// passed to the layer below.
+    pub fn gradient_alignment<E>(
+        &self,
+        inputs: &[F],
+        error: E,
+        output_gradient: &[F],
+    ) -> (LayerAlignment, Vec<F>)
+    where
+        E: Into<Amines<F>>,
+    {
+        self.weights
+            .gradient_alignment(&self.activation, error.into(), inputs, output_gradient)
+    }
+
+    /// Takes either a signed `output - target` delta or `Amines`, and returns the deltas of
+    /// the outputs after the activation derivative.
+    pub fn backward<E>(&mut self, error: E) -> Vec<F>
+    where
+        E: Into<Amines<F>>,
+    {
+        self.backward_with_rate(error, 1.)
+    }
+
+    /// `backward` with the error scaled by `learning_rate`. Unlike scaling the error beforehand,
+    /// the rate also reaches optimizers that normalize the steps, see `Optimizer::update`.
+    pub fn backward_with_rate<E>(&mut self, error: E, learning_rate: f64) -> Vec<F>
+    where
+        E: Into<Amines<F>>,
+    {
+        self.weights.backward(
+            &self.activation,
+            error.into() * F::cast(learning_rate),
+            &self.last_inputs[0],
+            &mut self.optimizer,
+            learning_rate,
+        )
+    }
+
+    /// Accumulates the ED steps of the last mini-batch, one error per sample, and applies them
+    /// once instead of after every sample. Panics on an empty batch.
+    pub fn backward_batch<E>(&mut self, errors: &[E], reduction: BatchReduction) -> Vec<Vec<F>>
+    where
+        E: Into<Amines<F>> + Copy,
+    {
+        self.backward_batch_with_rate(errors, reduction, 1.)
+    }
+
+    /// `backward_batch` with the errors scaled by `learning_rate`, see `backward_with_rate`.
+    pub fn backward_batch_with_rate<E>(
+        &mut self,
+        errors: &[E],
+        reduction: BatchReduction,
+        learning_rate: f64,
+    ) -> Vec<Vec<F>>
+    where
+        E: Into<Amines<F>> + Copy,
+    {
+        let errors: Vec<_> = errors
             .iter()
-            .map(|layer| layer.forward_without_train(&inputs))
-            .collect()
+            .map(|&error| error.into() * F::cast(learning_rate))
+            .collect();
+        let last_inputs: Vec<_> = self.last_inputs.iter().map(|inputs| &inputs[..]).collect();
+        self.weights.backward_batch(
+            &self.activation,
+            &errors,
+            &last_inputs,
+            reduction,
+            &mut self.optimizer,
+            learning_rate,
+        )
     }
+}
 
-    pub fn backward(&mut self, delta: f64) -> Vec<f64> {
-        self.inner_layers
-            .iter_mut()
-            .map(|layer| layer.backward(delta, &self.last_inputs))
+#[derive(Serialize, Deserialize)]
+#[serde(bound(deserialize = "ActivationFunc: Deserialize<'de>, F: Float"))]
+pub struct MultiOutputLayerState<ActivationFunc, F = f64> {
+    activation: ActivationFunc,
+    inner_layers: Vec<WeightMatrix<F>>,
+}
+
+// A `WeightMatrix` as written by version 1, which predates the layouts and always used
+// `Layout::Parity`.
+#[derive(Deserialize)]
+#[serde(bound(deserialize = "F: Float"))]
+pub struct WeightMatrixV1<F> {
+    input: usize,
+    output: usize,
+    weights: Vec<F>,
+    signs: Vec<F>,
+    bias: Option<Bias<F>>,
+    bias_weights: Vec<[F; 2]>,
+}
+
+impl<F: Float> From<WeightMatrixV1<F>> for WeightMatrix<F> {
+    fn from(matrix: WeightMatrixV1<F>) -> Self {
+        WeightMatrix {
+            input: matrix.input,
+            output: matrix.output,
+            weights: matrix.weights,
+            signs: matrix.signs,
+            input_types: Layout::Parity.types(matrix.input),
+            bias: matrix.bias,
+            bias_weights: matrix.bias_weights,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(bound(deserialize = "ActivationFunc: Deserialize<'de>, F: Float"))]
+pub struct MultiOutputLayerStateV1<ActivationFunc, F> {
+    activation: ActivationFunc,
+    inner_layers: Vec<WeightMatrixV1<F>>,
+}
+
+impl<ActivationFunc, F> VersionedState for MultiOutputLayerState<ActivationFunc, F>
+where
+    ActivationFunc: Serialize + DeserializeOwned,
+    F: Float,
+{
+    type V1 = MultiOutputLayerStateV1<ActivationFunc, F>;
+
+    fn from_v1(state: Self::V1) -> Self {
+        MultiOutputLayerState {
+            activation: state.activation,
+            inner_layers: state.inner_layers.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl<ActivationFunc, F> Persist for MultiOutputLayer<ActivationFunc, F>
+where
+    ActivationFunc: DifferentiableFn<F, Args = F> + PersistentActivation + Clone,
+    F: Float,
+{
+    const MODEL: &'static str = "MultiOutputLayer";
+    const PRECISION: &'static str = F::NAME;
+    type State = MultiOutputLayerState<ActivationFunc, F>;
+
+    fn activations() -> Vec<&'static str> {
+        vec![ActivationFunc::NAME]
+    }
+
+    fn state(&self) -> Self::State {
+        MultiOutputLayerState {
+            activation: self.activation.clone(),
+            inner_layers: self.inner_layers.clone(),
+        }
+    }
+
+    fn from_state(state: Self::State) -> Result<Self, ModelError> {
+        for layer in state.inner_layers.iter() {
+            layer.validate()?;
+        }
+        Ok(MultiOutputLayer {
+            optimizers: state.inner_layers.iter().map(|_| None).collect(),
+            schedule: ModelSchedule::default(),
+            inner_layers: state.inner_layers,
+            activation: state.activation,
+            last_inputs: Vec::new(),
+            parallel: false,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(bound(deserialize = "ActivationFunc: Deserialize<'de>, F: Float"))]
+pub struct LayerState<ActivationFunc, F = f64> {
+    activation: ActivationFunc,
+    weights: WeightMatrix<F>,
+}
+
+#[derive(Deserialize)]
+#[serde(bound(deserialize = "ActivationFunc: Deserialize<'de>, F: Float"))]
+pub struct LayerStateV1<ActivationFunc, F> {
+    activation: ActivationFunc,
+    weights: WeightMatrixV1<F>,
+}
+
+impl<ActivationFunc, F> VersionedState for LayerState<ActivationFunc, F>
+where
+    ActivationFunc: Serialize + DeserializeOwned,
+    F: Float,
+{
+    type V1 = LayerStateV1<ActivationFunc, F>;
+
+    fn from_v1(state: Self::V1) -> Self {
+        LayerState {
+            activation: state.activation,
+            weights: state.weights.into(),
+        }
+    }
+}
+
+impl<ActivationFunc, F> Persist for Layer<ActivationFunc, F>
+where
+    ActivationFunc: DifferentiableFn<F, Args = F> + PersistentActivation + Clone,
+    F: Float,
+{
+    const MODEL: &'static str = "Layer";
+    const PRECISION: &'static str = F::NAME;
+    type State = LayerState<ActivationFunc, F>;
+
+    fn activations() -> Vec<&'static str> {
+        vec![ActivationFunc::NAME]
+    }
+
+    fn state(&self) -> Self::State {
+        LayerState {
+            activation: self.activation.clone(),
+            weights: self.weights.clone(),
+        }
+    }
+
+    fn from_state(state: Self::State) -> Result<Self, ModelError> {
+        state.weights.validate()?;
+        Ok(Layer {
+            weights: state.weights,
+            activation: state.activation,
+            last_inputs: Vec::new(),
+            optimizer: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        differentiable_fn::{
+            DifferentiableFn, Elu, Gelu, HardSigmoid, LeakyRelu, Relu, Sigmoid, Softplus, Tanh,
+        },
+        loss_fn::BCELoss,
+        optimizer::{Adam, Momentum, Sgd},
+        schedule::{Constant, StepDecay},
+    };
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    const LEARNING_RATE: f64 = 0.5;
+
+    fn train_not(config: LayerConfig) -> Vec<f64> {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut layer = Layer::with_config(&mut rng, 2, 1, Sigmoid::default(), config);
+        let inputs = [0., 1.];
+        let targets = [1., 0.];
+
+        for _ in 0..500 {
+            for (&input, &target) in inputs.iter().zip(targets.iter()) {
+                let output = layer.forward(vec![input, input])[0];
+                let delta = BCELoss.derivative((output, target));
+                layer.backward(delta * LEARNING_RATE);
+            }
+        }
+
+        inputs
+            .iter()
+            .map(|&input| layer.forward_without_train(vec![input, input])[0])
             .collect()
     }
+
+    #[test]
+    fn test_without_bias() {
+        let outputs = train_not(LayerConfig::default());
+        assert_eq!(outputs[0], 0.5);
+    }
+
+    #[test]
+    fn test_bias() {
+        let outputs = train_not(LayerConfig {
+            bias: Some(0.8),
+            ..Default::default()
+        });
+        assert!(outputs[0] > 0.9, "{:?}", outputs);
+        assert!(outputs[1] < 0.1, "{:?}", outputs);
+    }
+
+    // Distances to the target before and after training, the `MultiOutputLayer` is checked to
+    // train its first sub-layer exactly like a `Layer`.
+    fn fit_target<F>(activation: F) -> (f64, f64)
+    where
+        F: DifferentiableFn<Args = f64> + Copy,
+    {
+        let config = LayerConfig {
+            bias: Some(0.8),
+            ..Default::default()
+        };
+        let inputs = vec![0.5, 0.5];
+        let target = 0.3;
+
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut layer = Layer::with_config(&mut rng, 2, 1, activation, config);
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut multi = MultiOutputLayer::with_inputs(&mut rng, &[2, 2], 1, activation, config);
+        let before = (layer.forward_without_train(inputs.clone())[0] - target).abs();
+        for _ in 0..1000 {
+            let output = layer.forward(inputs.clone())[0];
+            layer.backward((output - target) * 0.1);
+            let outputs = multi.forward(vec![inputs.clone(); 2]);
+            assert_eq!(outputs[0][0], output);
+            multi.backward(&[(output - target) * 0.1, 0.]);
+        }
+        let after = (layer.forward_without_train(inputs)[0] - target).abs();
+        (before, after)
+    }
+
+    #[test]
+    fn test_activations() {
+        let results = [
+            fit_target(Relu),
+            fit_target(LeakyRelu::default()),
+            fit_target(Tanh),
+            fit_target(Softplus),
+            fit_target(HardSigmoid),
+            fit_target(Elu::default()),
+            fit_target(Gelu),
+            fit_target(Sigmoid::with_u0(0.4)),
+        ];
+        for (before, after) in results {
+            assert!(after < before / 10., "{} -> {}", before, after);
+        }
+    }
+
+    fn new_layer() -> Layer<Sigmoid> {
+        let mut rng = StdRng::seed_from_u64(42);
+        let config = LayerConfig {
+            bias: Some(0.8),
+            ..Default::default()
+        };
+        Layer::with_config(&mut rng, 4, 3, Sigmoid::default(), config)
+    }
+
+    #[test]
+    fn test_sign_constraint() {
+        let train = |sign_constraint| {
+            let mut rng = StdRng::seed_from_u64(42);
+            let config = LayerConfig {
+                bias: Some(0.8),
+                sign_constraint,
+                ..Default::default()
+            };
+            let mut layer = Layer::with_config(&mut rng, 4, 3, Sigmoid::default(), config);
+            for i in 0..50 {
+                // ED steps only shrink a weight towards zero through negative inputs.
+                layer.forward(vec![-0.9, -0.9, 0.7, 0.7]);
+                layer.backward(if i % 2 == 0 { 2. } else { -2. });
+            }
+            layer
+        };
+        let wrong_signs = |layer: &Layer<Sigmoid>| {
+            let weights = &layer.weights;
+            let bias_signs = weights
+                .bias
+                .iter()
+                .flat_map(|bias| bias.signs.iter().flatten());
+            let weights_and_signs = weights
+                .weights
+                .iter()
+                .chain(weights.bias_weights.iter().flatten())
+                .zip(weights.signs.iter().chain(bias_signs));
+            weights_and_signs.filter(|(&w, &s)| w * s < 0.).count()
+        };
+
+        let unconstrained = train(SignConstraint::Unconstrained);
+        assert!(wrong_signs(&unconstrained) > 0);
+        assert!(unconstrained.sign_violations() >= wrong_signs(&unconstrained));
+        for sign_constraint in [
+            SignConstraint::Clamp,
+            SignConstraint::Reflect,
+            SignConstraint::Resample(1),
+        ] {
+            let layer = train(sign_constraint);
+            assert!(layer.sign_violations() > 0);
+            assert_eq!(wrong_signs(&layer), 0);
+        }
+        let inputs = vec![0.5; 4];
+        assert_eq!(
+            train(SignConstraint::Resample(1)).forward_without_train(inputs.clone()),
+            train(SignConstraint::Resample(1)).forward_without_train(inputs)
+        );
+
+        // Resampled weights come from the layer's initializer.
+        let config = LayerConfig {
+            sign_constraint: SignConstraint::Resample(1),
+            initializer: Initializer::Constant(0.25),
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut weights = WeightMatrix::<f64>::new(&mut rng, 4, 3, &config);
+        weights.weights[0] = -weights.signs[0];
+        weights.constrain_signs(None);
+        assert_eq!(weights.weights[0], 0.25 * weights.signs[0]);
+
+        // The layers of a stack don't share the streams of their own or neighbouring seeds.
+        let layer1 = SignConstraint::Resample(1).for_layer(1);
+        assert_ne!(layer1, SignConstraint::Resample(1).for_layer(0));
+        assert_ne!(layer1, SignConstraint::Resample(2).for_layer(0));
+        assert_ne!(layer1, SignConstraint::Resample(1));
+    }
+
+    #[test]
+    fn test_weight_decay() {
+        let train = |weight_decay| {
+            let mut layer = new_layer();
+            layer.set_weight_decay(weight_decay);
+            for _ in 0..20 {
+                layer.forward(vec![0., 0., 0., 0.]);
+                layer.backward(0.3);
+            }
+            layer
+        };
+        // Zero inputs leave the weights to the decay, only the bias weights are trained.
+        let initial = new_layer();
+        let undecayed = train(WeightDecay::default());
+        let l2 = train(WeightDecay { l1: 0., l2: 0.1 });
+        let l1 = train(WeightDecay { l1: 0.01, l2: 0. });
+
+        let weights = initial.weights.weights.iter();
+        for ((w, l2_w), l1_w) in weights.zip(&l2.weights.weights).zip(&l1.weights.weights) {
+            assert!((l2_w - w * 0.9f64.powi(20)).abs() < 1e-12);
+            assert!(l1_w * w >= 0. && (l1_w.abs() - (w.abs() - 0.2).max(0.)).abs() < 1e-12);
+        }
+        assert_eq!(undecayed.weights.weights, initial.weights.weights);
+        assert_eq!(l2.weights.bias_weights, undecayed.weights.bias_weights);
+
+        // A whole mini-batch decays once, whatever the scale of the deltas.
+        for delta in [0.3, 30.] {
+            let mut batched = new_layer();
+            batched.set_weight_decay(WeightDecay { l1: 0., l2: 0.1 });
+            batched.forward_batch(vec![vec![0., 0., 0., 0.]; 5]);
+            batched.backward_batch(&[delta; 5], BatchReduction::Sum);
+            let weights = initial.weights.weights.iter();
+            for (w, batched_w) in weights.zip(&batched.weights.weights) {
+                assert!((batched_w - w * 0.9).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn test_layouts() {
+        let new_layer = |layout| {
+            let mut rng = StdRng::seed_from_u64(42);
+            let config = LayerConfig {
+                bias: Some(0.8),
+                layout,
+                ..Default::default()
+            };
+            Layer::with_config(&mut rng, 6, 4, Sigmoid::default(), config)
+        };
+        let ratio = Layout::Ratio {
+            excitatory: 1,
+            inhibitory: 1,
+        };
+        assert_eq!(
+            new_layer(ratio).weights.weights,
+            new_layer(Layout::Parity).weights.weights
+        );
+
+        let layouts = [
+            Layout::Ratio {
+                excitatory: 2,
+                inhibitory: 1,
+            },
+            Layout::Random {
+                excitatory_fraction: 0.8,
+                seed: 1,
+            },
+            Layout::Blocks {
+                excitatory_fraction: 0.5,
+            },
+        ];
+        let inputs = vec![0.5; 6];
+        for layout in layouts {
+            let mut layer = new_layer(layout);
+            let before = layer.weights.weights.clone();
+            for j in 0..6 {
+                for k in 0..4 {
+                    let same_type = layout.is_excitatory(j, 6) == layout.is_excitatory(k, 4);
+                    assert_eq!(layer.weights.signs[j * 4 + k] > 0., same_type);
+                }
+            }
+
+            // A negative delta only releases the positive amine, which trains the synapses
+            // from excitatory inputs.
+            layer.forward(inputs.clone());
+            layer.backward(-0.5);
+            for (j, column) in layer.weights.weights.chunks(4).enumerate() {
+                let changed = column != &before[j * 4..(j + 1) * 4];
+                assert_eq!(changed, layout.is_excitatory(j, 6), "{:?}", layout);
+            }
+        }
+        let blocks = Layout::Blocks {
+            excitatory_fraction: 0.25,
+        };
+        let types: Vec<_> = (0..8).map(|i| blocks.is_excitatory(i, 8)).collect();
+        assert_eq!(
+            types,
+            [true, true, false, false, false, false, false, false]
+        );
+
+        // Adjacent seeds draw unrelated layouts, not shifted copies of each other.
+        let random = |seed| {
+            let layout = Layout::Random {
+                excitatory_fraction: 0.5,
+                seed,
+            };
+            (0..64).map(move |index| layout.is_excitatory(index, 64))
+        };
+        assert!(!random(1).skip(1).eq(random(2).take(63)));
+    }
+
+    #[test]
+    #[should_panic(expected = "excitatory_fraction must be in [0, 1]")]
+    fn test_invalid_layout() {
+        let config = LayerConfig {
+            layout: Layout::Blocks {
+                excitatory_fraction: 1.5,
+            },
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(42);
+        Layer::<Sigmoid>::with_config(&mut rng, 4, 3, Sigmoid::default(), config);
+    }
+
+    #[test]
+    fn test_version_1_binary() {
+        // Version 1 files had neither the precision nor the neuron types.
+        let layer = new_layer();
+        let weights = &layer.weights;
+        let mut bytes = b"EDNN".to_vec();
+        let envelope = (1u32, "Layer", vec!["Sigmoid"], &layer.activation);
+        let state = (
+            weights.input,
+            weights.output,
+            &weights.weights,
+            &weights.signs,
+        );
+        let bias = (&weights.bias, &weights.bias_weights);
+        bincode::serialize_into(&mut bytes, &(envelope, state, bias)).unwrap();
+
+        let loaded = Layer::<Sigmoid>::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.weights.input_types, Layout::Parity.types(4));
+        let inputs = vec![0.1, 0.1, 0.6, 0.6];
+        assert_eq!(
+            loaded.forward_without_train(inputs.clone()),
+            layer.forward_without_train(inputs)
+        );
+    }
+
+    #[test]
+    fn test_dropout() {
+        let new_layer = |noise| {
+            let mut rng = StdRng::seed_from_u64(42);
+            let config = LayerConfig {
+                noise,
+                ..Default::default()
+            };
+            Layer::with_config(&mut rng, 20, 20, Sigmoid::default(), config)
+        };
+        let noise = Noise {
+            input_dropout: 0.5,
+            output_dropout: 0.5,
+            ..Default::default()
+        };
+        let inputs = vec![0.5; 20];
+        let mut layer = new_layer(noise);
+        let mut same_seed = new_layer(noise);
+        let before = layer.weights.weights.clone();
+        let expected = layer.forward_without_train(inputs.clone());
+
+        let outputs = layer.forward(inputs.clone());
+        assert_eq!(same_seed.forward(inputs.clone()), outputs);
+        assert_ne!(layer.forward_without_train(inputs.clone()), outputs);
+        let dropped_outputs: Vec<_> = outputs.iter().map(|&output| output == 0.).collect();
+        let dropped_inputs: Vec<_> = layer.last_inputs[0].iter().map(|&x| x == 0.).collect();
+        assert!(dropped_outputs.contains(&true) && dropped_inputs.contains(&true));
+        // Sigmoid outputs of positive inputs are above 0.5, the kept ones are doubled.
+        for (output, expected) in outputs.iter().zip(&expected) {
+            assert!(*output == 0. || *output > *expected);
+        }
+
+        layer.backward(0.5);
+        layer.backward(-0.5);
+        for j in 0..20 {
+            for k in 0..20 {
+                let changed = layer.weights.weights[j * 20 + k] != before[j * 20 + k];
+                assert_eq!(changed, !dropped_inputs[j] && !dropped_outputs[k]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_input_noise() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut layer = Layer::<Sigmoid>::new(&mut rng, 4, 3);
+        let inputs = vec![0.5; 4];
+        let expected = layer.forward_without_train(inputs.clone());
+        layer.set_noise(
+            Noise {
+                input_std: 0.1,
+                ..Default::default()
+            },
+            1,
+        );
+        assert_eq!(layer.forward_without_train(inputs.clone()), expected);
+        assert_ne!(layer.forward(inputs.clone()), expected);
+        assert!(layer.last_inputs[0].iter().all(|&x| x != 0.5));
+
+        layer.set_noise(
+            Noise {
+                input_std: 1.,
+                ..Default::default()
+            },
+            1,
+        );
+        for _ in 0..10 {
+            layer.forward(vec![0.; 4]);
+            assert!(layer.last_inputs[0].iter().all(|&x| x >= 0.));
+        }
+    }
+
+    #[test]
+    fn test_single_sample_batch() {
+        let inputs = vec![0.2, 0.2, 0.7, 0.7];
+        let mut layer = new_layer();
+        let mut batch_layer = new_layer();
+
+        for delta in [0.3, -0.4, 0.1] {
+            let output = layer.forward(inputs.clone());
+            let batch_output = batch_layer.forward_batch(vec![inputs.clone()]);
+            assert_eq!(batch_output, vec![output]);
+
+            let deltas = layer.backward(delta);
+            let batch_deltas = batch_layer.backward_batch(&[delta], BatchReduction::Sum);
+            assert_eq!(batch_deltas, vec![deltas]);
+        }
+        assert_eq!(
+            batch_layer.forward_without_train(inputs.clone()),
+            layer.forward_without_train(inputs)
+        );
+    }
+
+    #[test]
+    fn test_batch_reduction() {
+        let inputs = vec![0.2, 0.2, 0.7, 0.7];
+        let mut mean_layer = new_layer();
+        let mut sum_layer = new_layer();
+        let mut single_layer = new_layer();
+
+        mean_layer.forward_batch(vec![inputs.clone(); 2]);
+        mean_layer.backward_batch(&[0.3, 0.3], BatchReduction::Mean);
+        sum_layer.forward_batch(vec![inputs.clone(); 2]);
+        sum_layer.backward_batch(&[0.15, 0.15], BatchReduction::Sum);
+        single_layer.forward(inputs.clone());
+        single_layer.backward(0.3);
+
+        let expected = single_layer.forward_without_train(inputs.clone());
+        for layer in [mean_layer, sum_layer] {
+            let outputs = layer.forward_without_train(inputs.clone());
+            for (output, expected) in outputs.iter().zip(expected.iter()) {
+                assert!((output - expected).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one sample")]
+    fn test_empty_batch() {
+        let mut layer = new_layer();
+        layer.forward_batch(Vec::new());
+        layer.backward_batch::<f64>(&[], BatchReduction::Mean);
+    }
+
+    #[test]
+    fn test_parallel_multi_output_layer() {
+        let new_layer = || {
+            let mut rng = StdRng::seed_from_u64(42);
+            MultiOutputLayer::<Sigmoid>::new(&mut rng, 10, 8, 4)
+        };
+        let mut layer = new_layer();
+        let mut parallel_layer = new_layer();
+        parallel_layer.set_parallel(true);
+
+        let inputs = vec![vec![0.1, 0.1, 0.5, 0.5, 0.0, 0.0, 0.9, 0.9]; 10];
+        for i in 0..10 {
+            let deltas: Vec<_> = (0..10).map(|j| (i + j) as f64 * 0.01 - 0.05).collect();
+            assert_eq!(
+                parallel_layer.forward(inputs.clone()),
+                layer.forward(inputs.clone())
+            );
+            parallel_layer.backward(&deltas);
+            layer.backward(&deltas);
+
+            let batch = vec![inputs.clone(); 3];
+            assert_eq!(
+                parallel_layer.forward_batch(batch.clone()),
+                layer.forward_batch(batch)
+            );
+            parallel_layer.backward_batch(&vec![deltas.clone(); 3], BatchReduction::Mean);
+            layer.backward_batch(&vec![deltas; 3], BatchReduction::Mean);
+        }
+        assert_eq!(
+            parallel_layer.forward_without_train(inputs.clone()),
+            layer.forward_without_train(inputs)
+        );
+    }
+
+    #[test]
+    fn test_multi_output_schedule() {
+        let new_layer = || {
+            let mut rng = StdRng::seed_from_u64(42);
+            MultiOutputLayer::<Sigmoid>::new(&mut rng, 2, 4, 3)
+        };
+        let mut layer = new_layer();
+        let mut scheduled_layer = new_layer();
+        scheduled_layer.set_schedule(StepDecay::new(LEARNING_RATE, 0.5, 1));
+
+        let inputs = vec![vec![0.3, 0.3, 0.8, 0.8]; 2];
+        for i in 0..4 {
+            let learning_rate = LEARNING_RATE * 0.5f64.powi(i);
+            assert_eq!(scheduled_layer.learning_rate(), Some(learning_rate));
+            let deltas = [0.2, -0.4];
+            layer.forward(inputs.clone());
+            layer.backward(&deltas.map(|delta| delta * learning_rate));
+            scheduled_layer.forward(inputs.clone());
+            scheduled_layer.backward(&deltas);
+
+            layer.forward_batch(vec![inputs.clone(); 2]);
+            layer.backward_batch(
+                &vec![deltas.map(|delta| delta * learning_rate).to_vec(); 2],
+                BatchReduction::Mean,
+            );
+            scheduled_layer.forward_batch(vec![inputs.clone(); 2]);
+            scheduled_layer.backward_batch(&vec![deltas.to_vec(); 2], BatchReduction::Mean);
+            scheduled_layer.step_schedule(None);
+        }
+        assert_eq!(
+            scheduled_layer.forward_without_train(inputs.clone()),
+            layer.forward_without_train(inputs)
+        );
+    }
+
+    #[test]
+    fn test_schedule_with_adam() {
+        let new_layer = || {
+            let mut rng = StdRng::seed_from_u64(42);
+            let mut layer = MultiOutputLayer::<Sigmoid>::new(&mut rng, 2, 4, 3);
+            layer.set_optimizer(Adam::new(0.01));
+            layer
+        };
+        let initial = new_layer();
+        let mut layer = new_layer();
+        let mut scaled_layer = new_layer();
+        let mut scheduled_layer = new_layer();
+        scheduled_layer.set_schedule(Constant::new(0.5));
+
+        let inputs = vec![vec![0.3, 0.3, 0.8, 0.8]; 2];
+        let deltas = [0.2, -0.4];
+        layer.forward(inputs.clone());
+        layer.backward(&deltas);
+        scaled_layer.forward(inputs.clone());
+        scaled_layer.backward(&deltas.map(|delta| delta * 0.5));
+        scheduled_layer.forward(inputs);
+        scheduled_layer.backward(&deltas);
+
+        // Adam normalizes the scaling of the deltas away, but follows the rate of a schedule.
+        let changes = |layer: &MultiOutputLayer<Sigmoid>| -> Vec<f64> {
+            layer
+                .inner_layers
+                .iter()
+                .zip(initial.inner_layers.iter())
+                .flat_map(|(layer, initial)| {
+                    layer
+                        .weights
+                        .iter()
+                        .zip(initial.weights.iter())
+                        .map(|(w, w0)| w - w0)
+                })
+                .collect()
+        };
+        let change = changes(&layer);
+        assert!(change.iter().any(|&change| change != 0.));
+        for ((change, scaled), scheduled) in change
+            .iter()
+            .zip(changes(&scaled_layer))
+            .zip(changes(&scheduled_layer))
+        {
+            assert!((scaled - change).abs() < 1e-6);
+            assert!((scheduled - 0.5 * change).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_heterogeneous_inputs() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let config = LayerConfig {
+            bias: Some(0.8),
+            ..Default::default()
+        };
+        let mut layer =
+            MultiOutputLayer::with_inputs(&mut rng, &[2, 4], 3, Sigmoid::default(), config);
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut layer0 = Layer::with_config(&mut rng, 2, 3, Sigmoid::default(), config);
+        let mut layer1 = Layer::with_config(&mut rng, 4, 3, Sigmoid::default(), config);
+        assert_eq!(layer.inputs(), vec![2, 4]);
+
+        let inputs0 = vec![0.3, 0.3];
+        let inputs1 = vec![0.0, 0.0, 0.9, 0.9];
+        let inputs = vec![inputs0.clone(), inputs1.clone()];
+        for _ in 0..3 {
+            let outputs = layer.forward(inputs.clone());
+            assert_eq!(outputs[0], layer0.forward(inputs0.clone()));
+            assert_eq!(outputs[1], layer1.forward(inputs1.clone()));
+            layer.backward(&[0.2, -0.4]);
+            layer0.backward(0.2);
+            layer1.backward(-0.4);
+
+            layer.forward_batch(vec![inputs.clone(); 2]);
+            layer0.forward_batch(vec![inputs0.clone(); 2]);
+            layer1.forward_batch(vec![inputs1.clone(); 2]);
+            layer.backward_batch(&[vec![0.1, 0.3], vec![-0.2, 0.1]], BatchReduction::Sum);
+            layer0.backward_batch(&[0.1, -0.2], BatchReduction::Sum);
+            layer1.backward_batch(&[0.3, 0.1], BatchReduction::Sum);
+        }
+        assert_eq!(
+            layer.forward_without_train(inputs),
+            vec![
+                layer0.forward_without_train(inputs0),
+                layer1.forward_without_train(inputs1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_amines() {
+        let inputs = vec![0.2, 0.2, 0.7, 0.7];
+        let mut layer = new_layer();
+        let mut split_layer = new_layer();
+
+        layer.forward(inputs.clone());
+        let deltas = layer.backward(Amines::new(0.3, 0.1));
+        split_layer.forward(inputs.clone());
+        split_layer.backward(Amines::new(0.3, 0.));
+        split_layer.backward(Amines::new(0., 0.1));
+        assert_eq!(
+            layer.forward_without_train(inputs.clone()),
+            split_layer.forward_without_train(inputs.clone())
+        );
+
+        let mut signed_layer = new_layer();
+        signed_layer.forward(inputs.clone());
+        assert_eq!(signed_layer.backward(0.1 - 0.3), deltas);
+        assert_eq!(Amines::from(-0.2), Amines::new(0.2, 0.));
+    }
+
+    #[test]
+    fn test_optimizer() {
+        let inputs = vec![0.2, 0.2, 0.7, 0.7];
+        let mut layer = new_layer();
+        let mut sgd_layer = new_layer();
+        sgd_layer.set_optimizer(Sgd);
+        let mut momentum_layer = new_layer();
+        momentum_layer.set_optimizer(Momentum::new(0.5));
+        let mut multi_layer =
+            MultiOutputLayer::<Sigmoid>::new(&mut StdRng::seed_from_u64(42), 2, 4, 3);
+        multi_layer.set_optimizer(Adam::new(0.01));
+
+        for delta in [0.3, -0.2, 0.1] {
+            layer.forward(inputs.clone());
+            layer.backward(delta);
+            sgd_layer.forward(inputs.clone());
+            sgd_layer.backward(delta);
+            momentum_layer.forward(inputs.clone());
+            momentum_layer.backward(delta);
+            multi_layer.forward(vec![inputs.clone(); 2]);
+            multi_layer.backward(&[delta, -delta]);
+        }
+        for (layer, batch_delta) in [(&mut layer, 0.1), (&mut sgd_layer, 0.1)] {
+            layer.forward_batch(vec![inputs.clone(); 2]);
+            layer.backward_batch(&[batch_delta, -0.2], BatchReduction::Mean);
+        }
+        assert_eq!(
+            sgd_layer.forward_without_train(inputs.clone()),
+            layer.forward_without_train(inputs.clone())
+        );
+        assert_ne!(
+            momentum_layer.forward_without_train(inputs.clone()),
+            layer.forward_without_train(inputs.clone())
+        );
+
+        // Every weight keeps the sign of its synapse.
+        for weights in [&momentum_layer.weights]
+            .into_iter()
+            .chain(multi_layer.inner_layers.iter())
+        {
+            for (w, s) in weights.weights.iter().zip(weights.signs.iter()) {
+                assert!(w * s >= 0.);
+            }
+        }
+    }
+
+    #[test]
+    fn test_f32() {
+        let config = LayerConfig {
+            bias: Some(0.5),
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut single =
+            Layer::<Sigmoid, f32>::with_config(&mut rng, 2, 1, Sigmoid::default(), config);
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut double = Layer::<Sigmoid>::with_config(&mut rng, 2, 1, Sigmoid::default(), config);
+
+        for _ in 0..500 {
+            for (input, target) in [(0., 1.), (1., 0.)] {
+                let output = single.forward(vec![input as f32; 2])[0];
+                single.backward(BCELoss.derivative((output, target as f32)) * LEARNING_RATE as f32);
+                let output = double.forward(vec![input; 2])[0];
+                double.backward(BCELoss.derivative((output, target)) * LEARNING_RATE);
+            }
+        }
+        for input in [0., 1.] {
+            let single = single.forward_without_train(vec![input as f32; 2])[0];
+            let double = double.forward_without_train(vec![input; 2])[0];
+            assert!(
+                (single as f64 - double).abs() < 1e-4,
+                "{} {}",
+                single,
+                double
+            );
+        }
+    }
 }
@@ -1,73 +1,496 @@
-use super::differentiable_fn::{DifferentiableFn, Sigmoid};
+use super::{
+    differentiable_fn::{logistic, DifferentiableFn, Sigmoid, Softplus},
+    float::Float,
+};
+use num_traits::clamp;
+
+/// Loss of a whole model output, used by `Trainer`. `delta` has the shape of the output.
+/// Element-wise losses implement it for scalar and vector outputs, the vector loss being the sum
+/// over the elements. The loss is `f64` whatever the precision of the output.
+pub trait Loss<Output> {
+    type Target;
+    fn loss(&self, output: &Output, target: &Self::Target) -> f64;
+    fn delta(&self, output: &Output, target: &Self::Target) -> Output;
+}
+
+// Implemented per precision rather than for every `F: Float`, so that the vector losses below
+// don't overlap with the element-wise ones.
+macro_rules! element_wise_loss {
+    ($float:ty) => {
+        impl<L> Loss<$float> for L
+        where
+            L: DifferentiableFn<$float, Args = ($float, $float)>,
+        {
+            type Target = $float;
+            fn loss(&self, &output: &$float, &target: &$float) -> f64 {
+                self.eval((output, target)).as_f64()
+            }
+            fn delta(&self, &output: &$float, &target: &$float) -> $float {
+                self.derivative((output, target))
+            }
+        }
+
+        impl<L> Loss<Vec<$float>> for L
+        where
+            L: DifferentiableFn<$float, Args = ($float, $float)>,
+        {
+            type Target = Vec<$float>;
+            fn loss(&self, output: &Vec<$float>, target: &Vec<$float>) -> f64 {
+                assert_eq!(output.len(), target.len());
+                output
+                    .iter()
+                    .zip(target)
+                    .map(|(&output, &target)| self.eval((output, target)).as_f64())
+                    .sum()
+            }
+            fn delta(&self, output: &Vec<$float>, target: &Vec<$float>) -> Vec<$float> {
+                assert_eq!(output.len(), target.len());
+                output
+                    .iter()
+                    .zip(target)
+                    .map(|(&output, &target)| self.derivative((output, target)))
+                    .collect()
+            }
+        }
+    };
+}
+element_wise_loss!(f32);
+element_wise_loss!(f64);
 
 pub struct BCELoss;
-impl DifferentiableFn for BCELoss {
-    type Args = (f64, f64);
-    fn eval((output, target): Self::Args) -> f64 {
-        let output = output.max(1e-12).min(1. - 1e-12);
-        -(target * output.ln() + (1. - target) * (1. - output).ln())
+impl BCELoss {
+    // Keeps the output away from 0 and 1, by more than the rounding of the precision.
+    fn clip<F: Float>(output: F) -> F {
+        let epsilon = F::cast(1e-12).max(F::epsilon());
+        clamp(output, epsilon, F::one() - epsilon)
     }
-    fn derivative((output, target): Self::Args) -> f64 {
-        let output = output.max(1e-12).min(1. - 1e-12);
-        -(target / output - (1.0 - target) / (1.0 - output))
+}
+impl<F: Float> DifferentiableFn<F> for BCELoss {
+    type Args = (F, F);
+    fn eval(&self, (output, target): Self::Args) -> F {
+        let output = BCELoss::clip(output);
+        -(target * output.ln() + (F::one() - target) * (F::one() - output).ln())
+    }
+    fn derivative(&self, (output, target): Self::Args) -> F {
+        let output = BCELoss::clip(output);
+        -(target / output - (F::one() - target) / (F::one() - output))
     }
 }
 
 pub struct BCEWithLogitsLoss;
-impl DifferentiableFn for BCEWithLogitsLoss {
-    type Args = (f64, f64);
-    fn eval((output, target): Self::Args) -> f64 {
-        let output = Sigmoid::eval(output);
-        BCELoss::eval((output, target))
+impl<F: Float> DifferentiableFn<F> for BCEWithLogitsLoss {
+    type Args = (F, F);
+    fn eval(&self, (output, target): Self::Args) -> F {
+        let output = Sigmoid::default().eval(output);
+        BCELoss.eval((output, target))
     }
-    fn derivative((output, target): Self::Args) -> f64 {
-        Sigmoid::eval(output) - target
+    fn derivative(&self, (output, target): Self::Args) -> F {
+        Sigmoid::default().eval(output) - target
     }
 }
 
 pub struct CrossEntropyLoss;
 impl CrossEntropyLoss {
-    pub fn softmax(input: &Vec<f64>) -> Vec<f64> {
-        let max = input.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
-        let exps: Vec<f64> = input.iter().map(|x| (x - max).exp()).collect();
-        let sum: f64 = exps.iter().sum();
-        exps.iter().map(|x| x / sum).collect()
+    pub fn softmax<F: Float>(input: &[F]) -> Vec<F> {
+        let max = input.iter().cloned().fold(F::neg_infinity(), F::max);
+        let exps: Vec<F> = input.iter().map(|&x| (x - max).exp()).collect();
+        let sum: F = exps.iter().sum();
+        exps.iter().map(|&x| x / sum).collect()
     }
+}
 
-    pub fn eval((output, target): (&Vec<f64>, &Vec<f64>)) -> f64 {
+impl<F: Float> Loss<Vec<F>> for CrossEntropyLoss {
+    type Target = Vec<F>;
+    fn loss(&self, output: &Vec<F>, target: &Vec<F>) -> f64 {
         let output = CrossEntropyLoss::softmax(output);
-        let epsilon = 1e-10;
+        let epsilon = F::cast(1e-10);
         -target
             .iter()
             .zip(output.iter())
-            .map(|(t, o)| {
-                if *t == 0.0 {
+            .map(|(&t, &o)| {
+                if t == F::zero() {
                     0.0
                 } else {
-                    *t * (o + epsilon).ln()
+                    (t * (o + epsilon).ln()).as_f64()
                 }
             })
             .sum::<f64>()
     }
-
-    pub fn derivative((output, target): (&Vec<f64>, &Vec<f64>)) -> Vec<f64> {
+    fn delta(&self, output: &Vec<F>, target: &Vec<F>) -> Vec<F> {
         let output = CrossEntropyLoss::softmax(output);
         output
             .iter()
             .zip(target.iter())
-            .map(|(o, t)| o - t)
+            .map(|(&o, &t)| o - t)
+            .collect()
+    }
+}
+
+/// Cross entropy against `(1 - smoothing) * target + smoothing / classes`.
+#[derive(Debug, Clone, Copy)]
+pub struct LabelSmoothingCrossEntropyLoss {
+    pub smoothing: f64,
+}
+impl LabelSmoothingCrossEntropyLoss {
+    pub fn new(smoothing: f64) -> Self {
+        assert!((0. ..=1.).contains(&smoothing));
+        LabelSmoothingCrossEntropyLoss { smoothing }
+    }
+
+    fn smooth<F: Float>(&self, target: &[F]) -> Vec<F> {
+        let uniform = F::cast(self.smoothing / target.len() as f64);
+        target
+            .iter()
+            .map(|&t| F::cast(1. - self.smoothing) * t + uniform)
             .collect()
     }
 }
+impl<F: Float> Loss<Vec<F>> for LabelSmoothingCrossEntropyLoss {
+    type Target = Vec<F>;
+    fn loss(&self, output: &Vec<F>, target: &Vec<F>) -> f64 {
+        CrossEntropyLoss.loss(output, &self.smooth(target))
+    }
+    fn delta(&self, output: &Vec<F>, target: &Vec<F>) -> Vec<F> {
+        CrossEntropyLoss.delta(output, &self.smooth(target))
+    }
+}
+
+/// Negative log likelihood of outputs that are already log probabilities, e.g. the logarithm
+/// of `CrossEntropyLoss::softmax`.
+pub struct NLLLoss;
+impl<F: Float> Loss<Vec<F>> for NLLLoss {
+    type Target = Vec<F>;
+    fn loss(&self, output: &Vec<F>, target: &Vec<F>) -> f64 {
+        -output
+            .iter()
+            .zip(target)
+            .map(|(&o, &t)| (o * t).as_f64())
+            .sum::<f64>()
+    }
+    fn delta(&self, _output: &Vec<F>, target: &Vec<F>) -> Vec<F> {
+        target.iter().map(|&t| -t).collect()
+    }
+}
 
 pub struct MSELoss;
-impl DifferentiableFn for MSELoss {
-    type Args = (f64, f64);
-    fn eval((output, target): Self::Args) -> f64 {
+impl<F: Float> DifferentiableFn<F> for MSELoss {
+    type Args = (F, F);
+    fn eval(&self, (output, target): Self::Args) -> F {
         (output - target).powi(2)
     }
-    fn derivative((output, target): Self::Args) -> f64 {
-        2. * (output - target)
+    fn derivative(&self, (output, target): Self::Args) -> F {
+        F::cast(2.) * (output - target)
+    }
+}
+
+/// Quadratic within `delta` of the target and linear beyond it.
+#[derive(Debug, Clone, Copy)]
+pub struct HuberLoss {
+    pub delta: f64,
+}
+impl HuberLoss {
+    pub fn new(delta: f64) -> Self {
+        assert!(delta > 0.);
+        HuberLoss { delta }
+    }
+}
+impl Default for HuberLoss {
+    fn default() -> Self {
+        HuberLoss::new(1.)
+    }
+}
+impl<F: Float> DifferentiableFn<F> for HuberLoss {
+    type Args = (F, F);
+    fn eval(&self, (output, target): Self::Args) -> F {
+        let (error, delta) = ((output - target).abs(), F::cast(self.delta));
+        if error <= delta {
+            F::cast(0.5) * error * error
+        } else {
+            delta * (error - F::cast(0.5) * delta)
+        }
+    }
+    fn derivative(&self, (output, target): Self::Args) -> F {
+        let delta = F::cast(self.delta);
+        clamp(output - target, -delta, delta)
+    }
+}
+
+/// `HuberLoss` divided by `beta`, so the linear part has slope 1.
+#[derive(Debug, Clone, Copy)]
+pub struct SmoothL1Loss {
+    pub beta: f64,
+}
+impl SmoothL1Loss {
+    pub fn new(beta: f64) -> Self {
+        assert!(beta > 0.);
+        SmoothL1Loss { beta }
+    }
+}
+impl Default for SmoothL1Loss {
+    fn default() -> Self {
+        SmoothL1Loss::new(1.)
+    }
+}
+impl<F: Float> DifferentiableFn<F> for SmoothL1Loss {
+    type Args = (F, F);
+    fn eval(&self, args: Self::Args) -> F {
+        HuberLoss::new(self.beta).eval(args) / F::cast(self.beta)
+    }
+    fn derivative(&self, args: Self::Args) -> F {
+        HuberLoss::new(self.beta).derivative(args) / F::cast(self.beta)
+    }
+}
+
+/// `max(0, 1 - target * output)` for targets of -1 and 1.
+pub struct HingeLoss;
+impl<F: Float> DifferentiableFn<F> for HingeLoss {
+    type Args = (F, F);
+    fn eval(&self, (output, target): Self::Args) -> F {
+        assert!(
+            target == F::one() || target == -F::one(),
+            "targets must be -1 or 1"
+        );
+        (F::one() - target * output).max(F::zero())
+    }
+    fn derivative(&self, (output, target): Self::Args) -> F {
+        assert!(
+            target == F::one() || target == -F::one(),
+            "targets must be -1 or 1"
+        );
+        if target * output < F::one() {
+            -target
+        } else {
+            F::zero()
+        }
+    }
+}
+
+/// `max(0, 1 - target * output)^2` for targets of -1 and 1.
+pub struct SquaredHingeLoss;
+impl<F: Float> DifferentiableFn<F> for SquaredHingeLoss {
+    type Args = (F, F);
+    fn eval(&self, args: Self::Args) -> F {
+        HingeLoss.eval(args).powi(2)
+    }
+    fn derivative(&self, (output, target): Self::Args) -> F {
+        F::cast(-2.) * target * HingeLoss.eval((output, target))
+    }
+}
+
+/// Binary focal loss of a logit, BCE with the term of each class scaled by
+/// `(1 - p)^gamma` where `p` is the predicted probability of that class.
+/// `gamma = 0` is `BCEWithLogitsLoss`.
+#[derive(Debug, Clone, Copy)]
+pub struct FocalLoss {
+    pub gamma: f64,
+}
+impl FocalLoss {
+    pub fn new(gamma: f64) -> Self {
+        assert!(gamma >= 0.);
+        FocalLoss { gamma }
+    }
+}
+impl Default for FocalLoss {
+    fn default() -> Self {
+        FocalLoss::new(2.)
+    }
+}
+impl<F: Float> DifferentiableFn<F> for FocalLoss {
+    type Args = (F, F);
+    fn eval(&self, (output, target): Self::Args) -> F {
+        let (p, gamma) = (logistic(output), F::cast(self.gamma));
+        // ln(p) and ln(1 - p) without overflow
+        let (ln_p, ln_q) = (-Softplus.eval(-output), -Softplus.eval(output));
+        -target * (F::one() - p).powf(gamma) * ln_p - (F::one() - target) * p.powf(gamma) * ln_q
+    }
+    fn derivative(&self, (output, target): Self::Args) -> F {
+        let (p, q) = (logistic(output), logistic(-output));
+        let (ln_p, ln_q) = (-Softplus.eval(-output), -Softplus.eval(output));
+        let gamma = F::cast(self.gamma);
+        target * q.powf(gamma) * (gamma * p * ln_p - q)
+            + (F::one() - target) * p.powf(gamma) * (p - gamma * q * ln_q)
+    }
+}
+
+/// Scales the loss of every sample by the weight of its class. A scalar target in `[0, 1]` is
+/// between the classes 0 and 1 and interpolates their weights, targets outside of it count as
+/// the nearest class, so -1 and 1 work for the hinge losses. A vector target averages the
+/// weights by its probabilities, which picks the weight of the class of a one-hot target.
+#[derive(Debug, Clone)]
+pub struct ClassWeighted<L> {
+    pub loss: L,
+    pub weights: Vec<f64>,
+}
+impl<L> ClassWeighted<L> {
+    pub fn new(loss: L, weights: Vec<f64>) -> Self {
+        assert!(weights.iter().all(|&weight| weight >= 0.));
+        ClassWeighted { loss, weights }
+    }
+
+    fn scalar_weight(&self, target: f64) -> f64 {
+        assert_eq!(self.weights.len(), 2, "a scalar target has two classes");
+        let target = target.clamp(0., 1.);
+        (1. - target) * self.weights[0] + target * self.weights[1]
+    }
+
+    fn vector_weight<F: Float>(&self, target: &[F]) -> f64 {
+        assert_eq!(self.weights.len(), target.len());
+        self.weights
+            .iter()
+            .zip(target)
+            .map(|(w, t)| w * t.as_f64())
+            .sum()
+    }
+}
+impl<F, L> Loss<F> for ClassWeighted<L>
+where
+    F: Float,
+    L: Loss<F, Target = F>,
+{
+    type Target = F;
+    fn loss(&self, output: &F, target: &F) -> f64 {
+        self.scalar_weight(target.as_f64()) * self.loss.loss(output, target)
+    }
+    fn delta(&self, output: &F, target: &F) -> F {
+        F::cast(self.scalar_weight(target.as_f64())) * self.loss.delta(output, target)
+    }
+}
+impl<F, L> Loss<Vec<F>> for ClassWeighted<L>
+where
+    F: Float,
+    L: Loss<Vec<F>, Target = Vec<F>>,
+{
+    type Target = Vec<F>;
+    fn loss(&self, output: &Vec<F>, target: &Vec<F>) -> f64 {
+        self.vector_weight(target) * self.loss.loss(output, target)
+    }
+    fn delta(&self, output: &Vec<F>, target: &Vec<F>) -> Vec<F> {
+        let weight = F::cast(self.vector_weight(target));
+        let delta = self.loss.delta(output, target);
+        delta.into_iter().map(|delta| weight * delta).collect()
+    }
+}
+
+/// Scales the loss of every sample by a weight given along with its target.
+#[derive(Debug, Clone, Copy)]
+pub struct SampleWeighted<L>(pub L);
+impl<F, L> Loss<F> for SampleWeighted<L>
+where
+    F: Float,
+    L: Loss<F>,
+{
+    type Target = (L::Target, f64);
+    fn loss(&self, output: &F, (target, weight): &Self::Target) -> f64 {
+        weight * self.0.loss(output, target)
+    }
+    fn delta(&self, output: &F, (target, weight): &Self::Target) -> F {
+        F::cast(*weight) * self.0.delta(output, target)
+    }
+}
+impl<F, L> Loss<Vec<F>> for SampleWeighted<L>
+where
+    F: Float,
+    L: Loss<Vec<F>>,
+{
+    type Target = (L::Target, f64);
+    fn loss(&self, output: &Vec<F>, (target, weight): &Self::Target) -> f64 {
+        weight * self.0.loss(output, target)
+    }
+    fn delta(&self, output: &Vec<F>, (target, weight): &Self::Target) -> Vec<F> {
+        let weight = F::cast(*weight);
+        let delta = self.0.delta(output, target);
+        delta.into_iter().map(|delta| weight * delta).collect()
+    }
+}
+
+/// Class weights inversely proportional to how often every class is labeled, scaled so that
+/// the weighted samples add up to the number of samples. Absent classes get weight 0.
+pub fn inverse_frequency_weights<I>(labels: I, classes: usize) -> Vec<f64>
+where
+    I: IntoIterator<Item = usize>,
+{
+    let mut counts = vec![0usize; classes];
+    for label in labels {
+        assert!(
+            label < classes,
+            "label {} out of {} classes",
+            label,
+            classes
+        );
+        counts[label] += 1;
+    }
+    let present = counts.iter().filter(|&&count| count > 0).count();
+    let total: usize = counts.iter().sum();
+    counts
+        .into_iter()
+        .map(|count| {
+            if count == 0 {
+                0.
+            } else {
+                total as f64 / (present * count) as f64
+            }
+        })
+        .collect()
+}
+
+/// Softmax cross entropy with the term of each class scaled by `(1 - p)^gamma`.
+/// `gamma = 0` is `CrossEntropyLoss`.
+#[derive(Debug, Clone, Copy)]
+pub struct SoftmaxFocalLoss {
+    pub gamma: f64,
+}
+impl SoftmaxFocalLoss {
+    pub fn new(gamma: f64) -> Self {
+        assert!(gamma >= 0.);
+        SoftmaxFocalLoss { gamma }
+    }
+
+    fn log_softmax<F: Float>(input: &[F]) -> Vec<F> {
+        let max = input.iter().cloned().fold(F::neg_infinity(), F::max);
+        let log_sum = input.iter().map(|&x| (x - max).exp()).sum::<F>().ln();
+        input.iter().map(|&x| x - max - log_sum).collect()
+    }
+}
+impl Default for SoftmaxFocalLoss {
+    fn default() -> Self {
+        SoftmaxFocalLoss::new(2.)
+    }
+}
+impl<F: Float> Loss<Vec<F>> for SoftmaxFocalLoss {
+    type Target = Vec<F>;
+    fn loss(&self, output: &Vec<F>, target: &Vec<F>) -> f64 {
+        let ln_p = SoftmaxFocalLoss::log_softmax(output);
+        let gamma = F::cast(self.gamma);
+        -target
+            .iter()
+            .zip(ln_p)
+            .map(|(&t, ln_p)| (t * (F::one() - ln_p.exp()).powf(gamma) * ln_p).as_f64())
+            .sum::<f64>()
+    }
+    fn delta(&self, output: &Vec<F>, target: &Vec<F>) -> Vec<F> {
+        let ln_p = SoftmaxFocalLoss::log_softmax(output);
+        let gamma = F::cast(self.gamma);
+        // The derivative of every class term with respect to its own log probability.
+        let terms: Vec<F> = ln_p
+            .iter()
+            .zip(target)
+            .map(|(&ln_p, &t)| {
+                let (p, q) = (ln_p.exp(), -ln_p.exp_m1());
+                // gamma * q^(gamma - 1) * p * ln(p) tends to 0 as q does, for any gamma > 0.
+                let focus = if self.gamma == 0. || q == F::zero() {
+                    F::zero()
+                } else {
+                    gamma * q.powf(gamma - F::one()) * p * ln_p
+                };
+                t * (focus - q.powf(gamma))
+            })
+            .collect();
+        let sum: F = terms.iter().sum();
+        terms
+            .iter()
+            .zip(ln_p)
+            .map(|(&term, ln_p)| term - ln_p.exp() * sum)
+            .collect()
     }
 }
 
@@ -89,7 +512,7 @@ mod tests {
     fn test_cross_entropy_loss() {
         let output = vec![1.0, 2.0, 3.0];
         let target = vec![0.0, 1.0, 0.0];
-        let loss = CrossEntropyLoss::eval((&output, &target));
+        let loss = CrossEntropyLoss.loss(&output, &target);
         assert_eq!(loss, 1.407605964035764);
     }
 
@@ -97,10 +520,198 @@ mod tests {
     fn test_cross_entropy_loss_derivative() {
         let output = vec![1.0, 2.0, 3.0];
         let target = vec![0.0, 1.0, 0.0];
-        let derivative = CrossEntropyLoss::derivative((&output, &target));
+        let derivative = CrossEntropyLoss.delta(&output, &target);
         assert_eq!(
             derivative,
             vec![0.09003057317038046, -0.7552715289452023, 0.6652409557748218]
         );
     }
+
+    #[test]
+    fn test_element_wise() {
+        let output = vec![0.2, 0.9];
+        let target = vec![0., 1.];
+        assert_eq!(
+            MSELoss.loss(&output, &target),
+            MSELoss.eval((0.2, 0.)) + MSELoss.eval((0.9, 1.))
+        );
+        assert_eq!(
+            BCELoss.delta(&output, &target),
+            vec![BCELoss.derivative((0.2, 0.)), BCELoss.derivative((0.9, 1.))]
+        );
+    }
+
+    #[test]
+    fn test_label_smoothing() {
+        let output = vec![1.0, 2.0, 3.0];
+        let target = vec![0.0, 1.0, 0.0];
+        let unsmoothed = LabelSmoothingCrossEntropyLoss::new(0.);
+        assert_eq!(
+            unsmoothed.loss(&output, &target),
+            CrossEntropyLoss.loss(&output, &target)
+        );
+
+        let smoothed = LabelSmoothingCrossEntropyLoss::new(0.3);
+        let expected = CrossEntropyLoss.loss(&output, &vec![0.1, 0.8, 0.1]);
+        assert!((smoothed.loss(&output, &target) - expected).abs() < 1e-12);
+        // The softmax gradient still sums to zero against the smoothed distribution.
+        let delta = smoothed.delta(&output, &target);
+        assert!(delta.iter().sum::<f64>().abs() < 1e-12);
+        assert!((delta[1] - (0.24472847105479764 - 0.8)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_nll_loss() {
+        let output: Vec<f64> = vec![1.0, 2.0, 3.0];
+        let target = vec![0.0, 1.0, 0.0];
+        let log_probabilities = CrossEntropyLoss::softmax(&output)
+            .iter()
+            .map(|p| p.ln())
+            .collect();
+        let loss = NLLLoss.loss(&log_probabilities, &target);
+        assert!((loss - CrossEntropyLoss.loss(&output, &target)).abs() < 1e-9);
+        assert_eq!(
+            NLLLoss.delta(&log_probabilities, &target),
+            vec![0., -1., 0.]
+        );
+    }
+
+    #[test]
+    fn test_huber_and_hinge() {
+        let huber = HuberLoss::new(0.5);
+        assert_eq!(huber.eval((0.3, 0.)), 0.045);
+        assert_eq!(huber.eval((-2., 0.)), 0.875);
+        assert_eq!(huber.derivative((-2., 0.)), -0.5);
+        let smooth_l1 = SmoothL1Loss::new(0.5);
+        assert_eq!(smooth_l1.eval((-2., 0.)), 1.75);
+        assert_eq!(smooth_l1.derivative((3., 0.)), 1.);
+
+        assert_eq!(HingeLoss.eval((0.25, 1.)), 0.75);
+        assert_eq!(HingeLoss.derivative((0.25, -1.)), 1.);
+        assert_eq!(HingeLoss.derivative((2., 1.)), 0.);
+        assert_eq!(SquaredHingeLoss.eval((0.5, -1.)), 2.25);
+        assert_eq!(SquaredHingeLoss.derivative((0.5, -1.)), 3.);
+    }
+
+    #[test]
+    #[should_panic(expected = "targets must be -1 or 1")]
+    fn test_hinge_target() {
+        HingeLoss.derivative((0.5, 0.));
+    }
+
+    #[test]
+    fn test_focal_loss() {
+        let h = 1e-6;
+        for (output, target) in [(-1.3f64, 1.), (0.4, 0.), (2.2, 1.), (0.7, 0.3)] {
+            let bce = FocalLoss::new(0.);
+            assert!(
+                (bce.eval((output, target)) - BCEWithLogitsLoss.eval((output, target))).abs()
+                    < 1e-12
+            );
+            assert!(
+                (bce.derivative((output, target)) - BCEWithLogitsLoss.derivative((output, target)))
+                    .abs()
+                    < 1e-12
+            );
+
+            let focal = FocalLoss::default();
+            let numeric =
+                (focal.eval((output + h, target)) - focal.eval((output - h, target))) / (2. * h);
+            assert!((numeric - focal.derivative((output, target))).abs() < 1e-6);
+        }
+        // Confident correct predictions are down-weighted more than wrong ones.
+        let focal = FocalLoss::default();
+        assert!(focal.eval((3., 1.)) / BCEWithLogitsLoss.eval((3., 1.)) < 0.01);
+        assert!(focal.eval((-3., 1.)) / BCEWithLogitsLoss.eval((-3., 1.)) > 0.9);
+        assert!(
+            focal.eval((800f64, 0.)).is_finite() && focal.derivative((-800f64, 1.)).is_finite()
+        );
+    }
+
+    #[test]
+    fn test_softmax_focal_loss() {
+        let output: Vec<f64> = vec![1.0, 2.0, 3.0];
+        let target = vec![0.0, 1.0, 0.0];
+        let cross_entropy = SoftmaxFocalLoss::new(0.);
+        assert!((cross_entropy.loss(&output, &target) - 1.407605964035764).abs() < 1e-9);
+        let delta = cross_entropy.delta(&output, &target);
+        let expected = CrossEntropyLoss.delta(&output, &target);
+        assert!(delta
+            .iter()
+            .zip(&expected)
+            .all(|(d, e)| (d - e).abs() < 1e-12));
+
+        let h = 1e-6;
+        for gamma in [0.5, 2.] {
+            let focal = SoftmaxFocalLoss::new(gamma);
+            let target = vec![0.2, 0.8, 0.0];
+            let delta = focal.delta(&output, &target);
+            for i in 0..output.len() {
+                let (mut plus, mut minus) = (output.clone(), output.clone());
+                plus[i] += h;
+                minus[i] -= h;
+                let numeric = (focal.loss(&plus, &target) - focal.loss(&minus, &target)) / (2. * h);
+                assert!(
+                    (numeric - delta[i]).abs() < 1e-6,
+                    "{} {}",
+                    numeric,
+                    delta[i]
+                );
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "label 4 out of 4 classes")]
+    fn test_class_weights_label() {
+        inverse_frequency_weights([0, 4], 4);
+    }
+
+    #[test]
+    fn test_class_weights() {
+        let weights = inverse_frequency_weights([0, 1, 1, 1, 3], 4);
+        assert_eq!(weights, vec![5. / 3., 5. / 9., 0., 5. / 3.]);
+
+        let binary = ClassWeighted::new(BCEWithLogitsLoss, vec![3., 0.5]);
+        assert_eq!(
+            binary.delta(&0.4, &0.),
+            3. * BCEWithLogitsLoss.derivative((0.4, 0.))
+        );
+        assert_eq!(
+            binary.loss(&0.4, &1.),
+            0.5 * BCEWithLogitsLoss.eval((0.4, 1.))
+        );
+        let hinge = ClassWeighted::new(HingeLoss, vec![3., 0.5]);
+        assert_eq!(hinge.delta(&0.4, &-1.), 3.);
+
+        let output = vec![1.0, 2.0, 3.0];
+        let target = vec![0.0, 1.0, 0.0];
+        let cross_entropy = ClassWeighted::new(CrossEntropyLoss, vec![1., 2., 4.]);
+        assert_eq!(
+            cross_entropy.loss(&output, &target),
+            2. * CrossEntropyLoss.loss(&output, &target)
+        );
+        let unweighted = ClassWeighted::new(CrossEntropyLoss, vec![1.; 3]);
+        assert_eq!(
+            unweighted.delta(&output, &target),
+            CrossEntropyLoss.delta(&output, &target)
+        );
+    }
+
+    #[test]
+    fn test_sample_weights() {
+        let loss = SampleWeighted(MSELoss);
+        assert_eq!(loss.loss(&0.5, &(1., 0.)), 0.);
+        assert_eq!(loss.delta(&0.5, &(1., 3.)), -3.);
+
+        let output = vec![1.0, 2.0, 3.0];
+        let target = vec![0.0, 1.0, 0.0];
+        let loss = SampleWeighted(ClassWeighted::new(CrossEntropyLoss, vec![1., 2., 4.]));
+        let expected: Vec<f64> = CrossEntropyLoss
+            .delta(&output, &target)
+            .iter()
+            .map(|delta| 0.5 * 2. * delta)
+            .collect();
+        assert_eq!(loss.delta(&output, &(target, 0.5)), expected);
+    }
 }
@@ -1,38 +1,84 @@
 use super::{
+    alignment::LayerAlignment,
     differentiable_fn::{PassThrough, Sigmoid},
-    layer::Layer,
+    float::Float,
+    initializer::Initializer,
+    layer::{BatchReduction, Layer, LayerConfig, LayerState, LayerStateV1, Layout},
+    persistence::{check_dims, ModelError, Persist, PersistentActivation, VersionedState},
+    schedule::{ModelSchedule, Schedule},
+    trainer::Model,
     util::duplicate_elements,
 };
 use rand::{rngs::StdRng, SeedableRng};
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug)]
-pub struct Mnist {
-    first_layer: Layer<Sigmoid>,
-    layers: Vec<Layer<Sigmoid>>,
-    last_layer: Layer<PassThrough>,
+pub struct Mnist<F = f64>
+where
+    F: Float,
+{
+    first_layer: Layer<Sigmoid, F>,
+    layers: Vec<Layer<Sigmoid, F>>,
+    last_layer: Layer<PassThrough, F>,
+    schedule: ModelSchedule,
 }
 
-impl Mnist {
+impl<F: Float> Mnist<F> {
     pub fn new(layer_num: usize, neural_num: usize) -> Self {
+        Self::with_initializer(layer_num, neural_num, Initializer::default())
+    }
+
+    pub fn with_initializer(layer_num: usize, neural_num: usize, initializer: Initializer) -> Self {
         let mut rng = StdRng::seed_from_u64(42);
+        // The default `Layout::Parity` pairs the inputs duplicated by `forward`.
+        let config = LayerConfig {
+            initializer,
+            ..Default::default()
+        };
         Mnist {
-            first_layer: Layer::new(&mut rng, 784 * 2, neural_num),
+            first_layer: Layer::with_config(
+                &mut rng,
+                784 * 2,
+                neural_num,
+                Sigmoid::default(),
+                config,
+            ),
             layers: (0..layer_num)
-                .map(|_| Layer::new(&mut rng, neural_num, neural_num))
+                .map(|_| {
+                    Layer::with_config(&mut rng, neural_num, neural_num, Sigmoid::default(), config)
+                })
                 .collect(),
-            last_layer: Layer::new(&mut rng, neural_num, 1),
+            last_layer: Layer::with_config(&mut rng, neural_num, 1, PassThrough, config),
+            schedule: ModelSchedule::default(),
         }
     }
 
-    pub fn forward(&mut self, inputs: &[f64]) -> f64 {
-        let x = duplicate_elements(inputs.into_iter()).collect();
+    /// The deltas passed to `backward` are scaled by the rate of `schedule`, which the caller
+    /// advances with `step_schedule`.
+    pub fn set_schedule<S>(&mut self, schedule: S)
+    where
+        S: Schedule + 'static,
+    {
+        self.schedule.set(schedule);
+    }
+
+    pub fn learning_rate(&self) -> Option<f64> {
+        self.schedule.learning_rate()
+    }
+
+    pub fn step_schedule(&mut self, metric: Option<f64>) {
+        self.schedule.step(metric);
+    }
+
+    pub fn forward(&mut self, inputs: &[F]) -> F {
+        let x = duplicate_elements(inputs.iter()).collect();
         let x = self.first_layer.forward(x);
         let x = self.layers.iter_mut().fold(x, |x, layer| layer.forward(x));
         self.last_layer.forward(x)[0]
     }
 
-    pub fn forward_without_train(&self, inputs: &[f64]) -> f64 {
-        let x = duplicate_elements(inputs.into_iter()).collect();
+    pub fn forward_without_train(&self, inputs: &[F]) -> F {
+        let x = duplicate_elements(inputs.iter()).collect();
         let x = self.first_layer.forward_without_train(x);
         let x = self
             .layers
@@ -41,11 +87,169 @@ impl Mnist {
         self.last_layer.forward_without_train(x)[0]
     }
 
-    pub fn backward(&mut self, delta: f64) {
-        self.first_layer.backward(delta);
+    pub fn backward(&mut self, delta: F) {
+        let rate = self.schedule.rate();
+        self.first_layer.backward_with_rate(delta, rate);
         self.layers.iter_mut().for_each(|layer| {
-            layer.backward(delta);
+            layer.backward_with_rate(delta, rate);
         });
-        self.last_layer.backward(delta);
+        self.last_layer.backward_with_rate(delta, rate);
+    }
+
+    /// Compares the update `backward` would make for one sample with backprop, layer by layer,
+    /// see `Sequential::gradient_alignment`.
+    pub fn gradient_alignment(&self, inputs: &[F], delta: F) -> Vec<LayerAlignment> {
+        let x: Vec<_> = duplicate_elements(inputs.iter()).collect();
+        let mut layer_inputs = vec![self.first_layer.forward_without_train(x.clone())];
+        for layer in self.layers.iter() {
+            let x = layer.forward_without_train(layer_inputs.last().unwrap().clone());
+            layer_inputs.push(x);
+        }
+
+        let (last_alignment, mut gradient) = self.last_layer.gradient_alignment(
+            layer_inputs.pop().unwrap().as_slice(),
+            delta,
+            &[delta],
+        );
+        let mut alignments = vec![last_alignment];
+        for (layer, inputs) in self.layers.iter().zip(layer_inputs.iter()).rev() {
+            let (alignment, input_gradient) = layer.gradient_alignment(inputs, delta, &gradient);
+            alignments.push(alignment);
+            gradient = input_gradient;
+        }
+        alignments.push(self.first_layer.gradient_alignment(&x, delta, &gradient).0);
+        alignments.reverse();
+        alignments
+    }
+
+    pub fn forward_batch(&mut self, inputs: &[&[F]]) -> Vec<F> {
+        let x = inputs
+            .iter()
+            .map(|inputs| duplicate_elements(inputs.iter()).collect())
+            .collect();
+        let x = self.first_layer.forward_batch(x);
+        let x = self
+            .layers
+            .iter_mut()
+            .fold(x, |x, layer| layer.forward_batch(x));
+        self.last_layer
+            .forward_batch(x)
+            .into_iter()
+            .map(|x| x[0])
+            .collect()
+    }
+
+    pub fn backward_batch(&mut self, deltas: &[F], reduction: BatchReduction) {
+        let rate = self.schedule.rate();
+        self.first_layer
+            .backward_batch_with_rate(deltas, reduction, rate);
+        self.layers.iter_mut().for_each(|layer| {
+            layer.backward_batch_with_rate(deltas, reduction, rate);
+        });
+        self.last_layer
+            .backward_batch_with_rate(deltas, reduction, rate);
+    }
+}
+
+impl<F: Float> Model<F> for Mnist<F> {
+    type Output = F;
+
+    fn forward(&mut self, inputs: &[F]) -> F {
+        Mnist::forward(self, inputs)
+    }
+    fn forward_without_train(&self, inputs: &[F]) -> F {
+        Mnist::forward_without_train(self, inputs)
+    }
+    fn backward(&mut self, &delta: &F) {
+        Mnist::backward(self, delta);
+    }
+    fn learning_rate(&self) -> Option<f64> {
+        Mnist::learning_rate(self)
+    }
+    fn step_schedule(&mut self, metric: Option<f64>) {
+        Mnist::step_schedule(self, metric);
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(bound(deserialize = "F: Float"))]
+pub struct MnistState<F = f64> {
+    first_layer: LayerState<Sigmoid, F>,
+    layers: Vec<LayerState<Sigmoid, F>>,
+    last_layer: LayerState<PassThrough, F>,
+}
+
+#[derive(Deserialize)]
+#[serde(bound(deserialize = "F: Float"))]
+pub struct MnistStateV1<F> {
+    first_layer: LayerStateV1<Sigmoid, F>,
+    layers: Vec<LayerStateV1<Sigmoid, F>>,
+    last_layer: LayerStateV1<PassThrough, F>,
+}
+
+impl<F: Float> VersionedState for MnistState<F> {
+    type V1 = MnistStateV1<F>;
+
+    fn from_v1(state: Self::V1) -> Self {
+        MnistState {
+            first_layer: LayerState::from_v1(state.first_layer),
+            layers: state.layers.into_iter().map(LayerState::from_v1).collect(),
+            last_layer: LayerState::from_v1(state.last_layer),
+        }
+    }
+}
+
+impl<F: Float> Persist for Mnist<F> {
+    const MODEL: &'static str = "Mnist";
+    const PRECISION: &'static str = F::NAME;
+    type State = MnistState<F>;
+
+    fn activations() -> Vec<&'static str> {
+        vec![Sigmoid::NAME, Sigmoid::NAME, PassThrough::NAME]
+    }
+
+    fn state(&self) -> Self::State {
+        MnistState {
+            first_layer: self.first_layer.state(),
+            layers: self.layers.iter().map(|layer| layer.state()).collect(),
+            last_layer: self.last_layer.state(),
+        }
+    }
+
+    fn from_state(state: Self::State) -> Result<Self, ModelError> {
+        let mnist = Mnist {
+            first_layer: Layer::from_state(state.first_layer)?,
+            layers: state
+                .layers
+                .into_iter()
+                .map(Layer::from_state)
+                .collect::<Result<_, _>>()?,
+            last_layer: Layer::from_state(state.last_layer)?,
+            schedule: ModelSchedule::default(),
+        };
+        if !mnist.first_layer.has_layout(Layout::Parity) {
+            return Err(ModelError::ArchitectureMismatch(
+                "first_layer must use Layout::Parity for the duplicated inputs".to_string(),
+            ));
+        }
+        let neural_num = mnist.first_layer.output();
+        check_dims(
+            "first_layer",
+            (mnist.first_layer.input(), neural_num),
+            (784 * 2, neural_num),
+        )?;
+        for (i, layer) in mnist.layers.iter().enumerate() {
+            check_dims(
+                &format!("layers[{}]", i),
+                (layer.input(), layer.output()),
+                (neural_num, neural_num),
+            )?;
+        }
+        check_dims(
+            "last_layer",
+            (mnist.last_layer.input(), mnist.last_layer.output()),
+            (neural_num, 1),
+        )?;
+        Ok(mnist)
     }
 }
@@ -0,0 +1,170 @@
+use super::float::Float;
+use std::fmt::Debug;
+
+/// Turns the ED steps of one update into the changes added to the weights, keeping any
+/// per-weight state. With non-negative inputs, e.g. from a sigmoid or pixel intensities, every
+/// step has the sign of its synapse (or is zero), so an optimizer that only rescales and averages
+/// the steps of each weight keeps ED's excitatory/inhibitory signs. Negative inputs flip their
+/// steps; use a `SignConstraint` to keep the signs then.
+/// The optimizers below keep their state in the precision of the steps.
+pub trait Optimizer<F = f64>: Send + Sync + Debug {
+    /// `steps` holds one entry per weight, followed by the bias weights, in a fixed order. They
+    /// are already scaled by `learning_rate`, the rate of the model's schedule or 1. Optimizers
+    /// that normalize the steps would cancel it, so they scale their output by it instead.
+    fn update(&mut self, steps: &mut [F], learning_rate: f64);
+}
+
+fn resize<F: Float>(state: &mut Vec<F>, size: usize) {
+    if state.len() != size {
+        *state = vec![F::zero(); size];
+    }
+}
+
+/// Plain SGD, the steps are applied unchanged.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sgd;
+impl<F: Float> Optimizer<F> for Sgd {
+    fn update(&mut self, _: &mut [F], _: f64) {}
+}
+
+#[derive(Debug, Clone)]
+pub struct Momentum<F = f64> {
+    pub momentum: f64,
+    velocity: Vec<F>,
+}
+impl<F> Momentum<F> {
+    pub fn new(momentum: f64) -> Self {
+        Momentum {
+            momentum,
+            velocity: Vec::new(),
+        }
+    }
+}
+impl<F: Float> Optimizer<F> for Momentum<F> {
+    fn update(&mut self, steps: &mut [F], _: f64) {
+        resize(&mut self.velocity, steps.len());
+        let momentum = F::cast(self.momentum);
+        for (step, velocity) in steps.iter_mut().zip(self.velocity.iter_mut()) {
+            *velocity = momentum * *velocity + *step;
+            *step = *velocity;
+        }
+    }
+}
+
+/// Divides every step by its running RMS, so only the rate of a schedule passed to `update`
+/// changes the size of the updates, not a scaling of the deltas.
+#[derive(Debug, Clone)]
+pub struct RmsProp<F = f64> {
+    pub learning_rate: f64,
+    pub decay: f64,
+    pub epsilon: f64,
+    mean_square: Vec<F>,
+}
+impl<F> RmsProp<F> {
+    pub fn new(learning_rate: f64) -> Self {
+        RmsProp {
+            learning_rate,
+            decay: 0.9,
+            epsilon: 1e-8,
+            mean_square: Vec::new(),
+        }
+    }
+}
+impl<F: Float> Optimizer<F> for RmsProp<F> {
+    fn update(&mut self, steps: &mut [F], learning_rate: f64) {
+        resize(&mut self.mean_square, steps.len());
+        let learning_rate = F::cast(self.learning_rate * learning_rate);
+        let decay = F::cast(self.decay);
+        let epsilon = F::cast(self.epsilon);
+        for (step, mean_square) in steps.iter_mut().zip(self.mean_square.iter_mut()) {
+            *mean_square = decay * *mean_square + (F::one() - decay) * *step * *step;
+            *step = learning_rate * *step / (mean_square.sqrt() + epsilon);
+        }
+    }
+}
+
+/// Like `RmsProp`, the updates only follow the rate of a schedule passed to `update`.
+#[derive(Debug, Clone)]
+pub struct Adam<F = f64> {
+    pub learning_rate: f64,
+    pub beta1: f64,
+    pub beta2: f64,
+    pub epsilon: f64,
+    moments: [Vec<F>; 2],
+    t: i32,
+}
+impl<F> Adam<F> {
+    pub fn new(learning_rate: f64) -> Self {
+        Adam {
+            learning_rate,
+            beta1: 0.9,
+            beta2: 0.999,
+            epsilon: 1e-8,
+            moments: [Vec::new(), Vec::new()],
+            t: 0,
+        }
+    }
+}
+impl<F: Float> Optimizer<F> for Adam<F> {
+    fn update(&mut self, steps: &mut [F], learning_rate: f64) {
+        if self.moments[0].len() != steps.len() {
+            self.t = 0;
+        }
+        resize(&mut self.moments[0], steps.len());
+        resize(&mut self.moments[1], steps.len());
+        self.t += 1;
+        let correction1 = F::cast(1. - self.beta1.powi(self.t));
+        let correction2 = F::cast(1. - self.beta2.powi(self.t));
+        let (beta1, beta2) = (F::cast(self.beta1), F::cast(self.beta2));
+        let learning_rate = F::cast(self.learning_rate * learning_rate);
+        let epsilon = F::cast(self.epsilon);
+
+        let [m, v] = &mut self.moments;
+        for ((step, m), v) in steps.iter_mut().zip(m.iter_mut()).zip(v.iter_mut()) {
+            *m = beta1 * *m + (F::one() - beta1) * *step;
+            *v = beta2 * *v + (F::one() - beta2) * *step * *step;
+            let m = *m / correction1;
+            let v = *v / correction2;
+            *step = learning_rate * m / (v.sqrt() + epsilon);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_momentum() {
+        let mut optimizer = Momentum::new(0.5);
+        let mut steps = [1., -2.];
+        optimizer.update(&mut steps, 1.);
+        assert_eq!(steps, [1., -2.]);
+        let mut steps = [1., 0.];
+        optimizer.update(&mut steps, 1.);
+        assert_eq!(steps, [1.5, -1.]);
+    }
+
+    #[test]
+    fn test_adaptive_keep_signs() {
+        let mut rms_prop = RmsProp::new(0.01);
+        let mut adam = Adam::new(0.01);
+        for i in 0..10 {
+            let steps = [0.3 / (i + 1) as f64, -0.001, 0.];
+            for optimizer in [&mut rms_prop as &mut dyn Optimizer, &mut adam] {
+                let mut updated = steps;
+                optimizer.update(&mut updated, 1.);
+                assert!(updated[0] > 0. && updated[1] < 0. && updated[2] == 0.);
+            }
+        }
+        // Adam's first step is the learning rate in the direction of the step.
+        let mut steps = [0.3f64, -0.001];
+        Adam::new(0.01).update(&mut steps, 1.);
+        assert!((steps[0] - 0.01).abs() < 1e-9 && (steps[1] + 0.01).abs() < 1e-6);
+
+        // The state is kept in the precision of the steps.
+        let mut steps = [0.3f32, -0.001];
+        Adam::<f32>::new(0.01).update(&mut steps, 1.);
+        assert!((steps[0] - 0.01).abs() < 1e-6 && (steps[1] + 0.01).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,458 @@
+use super::float::Float;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{error, fmt, fs, io, path::Path};
+
+pub const FORMAT_VERSION: u32 = 3;
+const MAGIC: &[u8; 4] = b"EDNN";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Binary,
+    Json,
+}
+
+#[derive(Debug)]
+pub enum ModelError {
+    Io(io::Error),
+    Encoding(String),
+    UnsupportedVersion(u32),
+    ModelMismatch {
+        expected: String,
+        found: String,
+    },
+    ActivationMismatch {
+        expected: Vec<String>,
+        found: Vec<String>,
+    },
+    ArchitectureMismatch(String),
+    PrecisionMismatch {
+        expected: String,
+        found: String,
+    },
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModelError::Io(err) => write!(f, "io error: {}", err),
+            ModelError::Encoding(err) => write!(f, "invalid model file: {}", err),
+            ModelError::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported format version {}, expected at most {}",
+                version, FORMAT_VERSION
+            ),
+            ModelError::ModelMismatch { expected, found } => {
+                write!(f, "model mismatch: expected {}, found {}", expected, found)
+            }
+            ModelError::ActivationMismatch { expected, found } => write!(
+                f,
+                "activation mismatch: expected {:?}, found {:?}",
+                expected, found
+            ),
+            ModelError::ArchitectureMismatch(err) => write!(f, "architecture mismatch: {}", err),
+            ModelError::PrecisionMismatch { expected, found } => {
+                write!(
+                    f,
+                    "precision mismatch: expected {}, found {}",
+                    expected, found
+                )
+            }
+        }
+    }
+}
+
+impl error::Error for ModelError {}
+
+impl From<io::Error> for ModelError {
+    fn from(err: io::Error) -> Self {
+        ModelError::Io(err)
+    }
+}
+
+impl From<bincode::Error> for ModelError {
+    fn from(err: bincode::Error) -> Self {
+        ModelError::Encoding(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for ModelError {
+    fn from(err: serde_json::Error) -> Self {
+        ModelError::Encoding(err.to_string())
+    }
+}
+
+/// An activation that can be stored along with the weights. `NAME` identifies it in model
+/// files, its fields hold its parameters.
+pub trait PersistentActivation: Serialize + DeserializeOwned {
+    const NAME: &'static str;
+}
+
+/// A `Persist::State` that also decodes the files of version 1, which predate the neuron
+/// layouts. `V1` is the state as written then.
+pub trait VersionedState: Serialize + DeserializeOwned {
+    type V1: DeserializeOwned;
+
+    fn from_v1(state: Self::V1) -> Self;
+}
+
+impl<A, B> VersionedState for (A, B)
+where
+    A: VersionedState,
+    B: VersionedState,
+{
+    type V1 = (A::V1, B::V1);
+
+    fn from_v1((a, b): Self::V1) -> Self {
+        (A::from_v1(a), B::from_v1(b))
+    }
+}
+
+// The version is decoded first, as it decides how the rest of the file is decoded.
+#[derive(Deserialize)]
+struct Version {
+    version: u32,
+}
+
+#[derive(Deserialize)]
+struct Header {
+    // Already checked through `Version`, but part of the binary layout.
+    #[allow(dead_code)]
+    version: u32,
+    model: String,
+    activations: Vec<String>,
+    precision: String,
+}
+
+// The header and envelope of versions 1 and 2, which always held `f64` values.
+#[derive(Deserialize)]
+struct HeaderV2 {
+    #[allow(dead_code)]
+    version: u32,
+    model: String,
+    activations: Vec<String>,
+}
+
+impl From<HeaderV2> for Header {
+    fn from(header: HeaderV2) -> Self {
+        Header {
+            version: header.version,
+            model: header.model,
+            activations: header.activations,
+            precision: f64::NAME.to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct EnvelopeV2<State> {
+    #[allow(dead_code)]
+    version: u32,
+    #[allow(dead_code)]
+    model: String,
+    #[allow(dead_code)]
+    activations: Vec<String>,
+    state: State,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope<State> {
+    version: u32,
+    model: String,
+    activations: Vec<String>,
+    precision: String,
+    state: State,
+}
+
+fn deserialize<T: DeserializeOwned>(format: Format, bytes: &[u8]) -> Result<T, ModelError> {
+    match format {
+        Format::Binary => Ok(bincode::deserialize(bytes)?),
+        Format::Json => Ok(serde_json::from_slice(bytes)?),
+    }
+}
+
+/// Saves the weights and what `from_state` needs to rebuild the architecture. The training
+/// settings are not saved: loaded models have no optimizer, sign constraint, weight decay, noise
+/// or schedule until they are set again.
+pub trait Persist: Sized {
+    /// Identifies the model type in model files.
+    const MODEL: &'static str;
+    /// `Float::NAME` of the weights.
+    const PRECISION: &'static str;
+    type State: VersionedState;
+
+    /// Names of the activations, in layer order.
+    fn activations() -> Vec<&'static str>;
+    fn state(&self) -> Self::State;
+    fn from_state(state: Self::State) -> Result<Self, ModelError>;
+
+    fn to_bytes(&self, format: Format) -> Result<Vec<u8>, ModelError> {
+        let envelope = Envelope {
+            version: FORMAT_VERSION,
+            model: Self::MODEL.to_string(),
+            activations: Self::activations().into_iter().map(String::from).collect(),
+            precision: Self::PRECISION.to_string(),
+            state: self.state(),
+        };
+        match format {
+            Format::Binary => {
+                let mut bytes = MAGIC.to_vec();
+                bincode::serialize_into(&mut bytes, &envelope)?;
+                Ok(bytes)
+            }
+            Format::Json => Ok(serde_json::to_vec_pretty(&envelope)?),
+        }
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, ModelError> {
+        let (format, bytes) = match bytes.strip_prefix(MAGIC) {
+            Some(bytes) => (Format::Binary, bytes),
+            None => (Format::Json, bytes),
+        };
+        // The header is a prefix of the envelope, so it is checked before the state is decoded.
+        let state = match deserialize::<Version>(format, bytes)?.version {
+            1 => {
+                check_header::<Self>(&deserialize::<HeaderV2>(format, bytes)?.into())?;
+                let envelope: EnvelopeV2<<Self::State as VersionedState>::V1> =
+                    deserialize(format, bytes)?;
+                Self::State::from_v1(envelope.state)
+            }
+            2 => {
+                check_header::<Self>(&deserialize::<HeaderV2>(format, bytes)?.into())?;
+                deserialize::<EnvelopeV2<Self::State>>(format, bytes)?.state
+            }
+            FORMAT_VERSION => {
+                check_header::<Self>(&deserialize(format, bytes)?)?;
+                deserialize::<Envelope<Self::State>>(format, bytes)?.state
+            }
+            version => return Err(ModelError::UnsupportedVersion(version)),
+        };
+        Self::from_state(state)
+    }
+
+    fn save<P: AsRef<Path>>(&self, path: P, format: Format) -> Result<(), ModelError> {
+        fs::write(path, self.to_bytes(format)?)?;
+        Ok(())
+    }
+
+    fn load<P: AsRef<Path>>(path: P) -> Result<Self, ModelError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+}
+
+fn check_header<M: Persist>(header: &Header) -> Result<(), ModelError> {
+    if header.model != M::MODEL {
+        return Err(ModelError::ModelMismatch {
+            expected: M::MODEL.to_string(),
+            found: header.model.clone(),
+        });
+    }
+    let expected = M::activations();
+    if header.activations != expected {
+        return Err(ModelError::ActivationMismatch {
+            expected: expected.into_iter().map(String::from).collect(),
+            found: header.activations.clone(),
+        });
+    }
+    if header.precision != M::PRECISION {
+        return Err(ModelError::PrecisionMismatch {
+            expected: M::PRECISION.to_string(),
+            found: header.precision.clone(),
+        });
+    }
+    Ok(())
+}
+
+/// Fails with `ArchitectureMismatch` unless `layer` is `expected_input`x`expected_output`, for
+/// `Persist::from_state` impls that only fit one shape.
+pub fn check_dims(
+    layer: &str,
+    (input, output): (usize, usize),
+    (expected_input, expected_output): (usize, usize),
+) -> Result<(), ModelError> {
+    if (input, output) != (expected_input, expected_output) {
+        return Err(ModelError::ArchitectureMismatch(format!(
+            "{} is {}x{}, expected {}x{}",
+            layer, input, output, expected_input, expected_output
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        differentiable_fn::{PassThrough, Sigmoid},
+        gate::Gate,
+        layer::{Layer, LayerConfig, Layout},
+        mnist::Mnist,
+    };
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn new_layer() -> Layer<Sigmoid> {
+        let mut rng = StdRng::seed_from_u64(42);
+        let config = LayerConfig {
+            bias: Some(0.8),
+            ..Default::default()
+        };
+        Layer::with_config(&mut rng, 4, 3, Sigmoid::new(2.5), config)
+    }
+
+    #[test]
+    fn test_layer_round_trip() {
+        let inputs = vec![0.1, 0.1, 0.6, 0.6];
+        let mut layer = new_layer();
+        layer.forward(inputs.clone());
+        layer.backward(0.2);
+
+        for format in [Format::Binary, Format::Json] {
+            let bytes = layer.to_bytes(format).unwrap();
+            let mut loaded = Layer::<Sigmoid>::from_bytes(&bytes).unwrap();
+            assert_eq!(
+                loaded.forward_without_train(inputs.clone()),
+                layer.forward_without_train(inputs.clone())
+            );
+
+            loaded.forward(inputs.clone());
+            loaded.backward(0.2);
+            layer.forward(inputs.clone());
+            layer.backward(0.2);
+            assert_eq!(
+                loaded.forward_without_train(inputs.clone()),
+                layer.forward_without_train(inputs.clone())
+            );
+        }
+    }
+
+    #[test]
+    fn test_models_round_trip() {
+        let mut gate = Gate::<PassThrough>::new();
+        let bytes = gate.to_bytes(Format::Json).unwrap();
+        let mut loaded = Gate::<PassThrough>::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.forward(&[1., 0.]), gate.forward(&[1., 0.]));
+
+        let image = vec![0.5; 784];
+        let mnist = Mnist::new(2, 4);
+        let loaded = Mnist::from_bytes(&mnist.to_bytes(Format::Binary).unwrap()).unwrap();
+        assert_eq!(
+            loaded.forward_without_train(&image),
+            mnist.forward_without_train(&image)
+        );
+    }
+
+    #[test]
+    fn test_file_round_trip() {
+        let path = std::env::temp_dir().join("ed_test_file_round_trip.json");
+        let layer = new_layer();
+        layer.save(&path, Format::Json).unwrap();
+        let loaded = Layer::<Sigmoid>::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let inputs = vec![0.3, 0.3, 0.9, 0.9];
+        assert_eq!(
+            loaded.forward_without_train(inputs.clone()),
+            layer.forward_without_train(inputs)
+        );
+    }
+
+    #[test]
+    fn test_version_1() {
+        // Version 1 had no neuron types and always used the parity layout.
+        let layer = new_layer();
+        let mut json: serde_json::Value =
+            serde_json::from_slice(&layer.to_bytes(Format::Json).unwrap()).unwrap();
+        json["version"] = 1.into();
+        assert!(json.as_object_mut().unwrap().remove("precision").is_some());
+        let weights = json["state"]["weights"].as_object_mut().unwrap();
+        assert!(weights.remove("input_types").is_some());
+
+        let mut loaded = Layer::<Sigmoid>::from_bytes(&serde_json::to_vec(&json).unwrap()).unwrap();
+        let mut layer = layer;
+        let inputs = vec![0.1, 0.1, 0.6, 0.6];
+        for delta in [0.2, -0.3] {
+            loaded.forward(inputs.clone());
+            loaded.backward(delta);
+            layer.forward(inputs.clone());
+            layer.backward(delta);
+        }
+        assert_eq!(
+            loaded.forward_without_train(inputs.clone()),
+            layer.forward_without_train(inputs)
+        );
+    }
+
+    #[test]
+    fn test_mismatch() {
+        let bytes = new_layer().to_bytes(Format::Binary).unwrap();
+        assert!(matches!(
+            Layer::<PassThrough>::from_bytes(&bytes),
+            Err(ModelError::ActivationMismatch { .. })
+        ));
+        assert!(matches!(
+            Gate::<Sigmoid>::from_bytes(&bytes),
+            Err(ModelError::ModelMismatch { .. })
+        ));
+
+        let bytes = Gate::<Sigmoid>::new().to_bytes(Format::Json).unwrap();
+        assert!(matches!(
+            Gate::<PassThrough>::from_bytes(&bytes),
+            Err(ModelError::ActivationMismatch { .. })
+        ));
+
+        let mut rng = StdRng::seed_from_u64(42);
+        let layer = Layer::<Sigmoid, f32>::new(&mut rng, 4, 3);
+        for format in [Format::Binary, Format::Json] {
+            let bytes = layer.to_bytes(format).unwrap();
+            assert!(Layer::<Sigmoid, f32>::from_bytes(&bytes).is_ok());
+            assert!(matches!(
+                Layer::<Sigmoid>::from_bytes(&bytes),
+                Err(ModelError::PrecisionMismatch { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        let json = String::from_utf8(new_layer().to_bytes(Format::Json).unwrap()).unwrap();
+
+        let version = format!("\"version\": {}", FORMAT_VERSION);
+        let newer = json.replacen(&version, &format!("\"version\": {}", FORMAT_VERSION + 1), 1);
+        assert!(matches!(
+            Layer::<Sigmoid>::from_bytes(newer.as_bytes()),
+            Err(ModelError::UnsupportedVersion(version)) if version == FORMAT_VERSION + 1
+        ));
+
+        let resized = json.replacen("\"input\": 4", "\"input\": 5", 1);
+        assert!(matches!(
+            Layer::<Sigmoid>::from_bytes(resized.as_bytes()),
+            Err(ModelError::ArchitectureMismatch(_))
+        ));
+
+        let bytes = new_layer().to_bytes(Format::Binary).unwrap();
+        assert!(matches!(
+            Layer::<Sigmoid>::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(ModelError::Encoding(_))
+        ));
+
+        // Mnist duplicates its inputs for a first layer of parity layout.
+        let mut json: serde_json::Value =
+            serde_json::from_slice(&Mnist::<f64>::new(0, 4).to_bytes(Format::Json).unwrap())
+                .unwrap();
+        let config = LayerConfig {
+            layout: Layout::Blocks {
+                excitatory_fraction: 0.5,
+            },
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(42);
+        let first_layer =
+            Layer::<Sigmoid>::with_config(&mut rng, 784 * 2, 4, Sigmoid::default(), config);
+        let first_layer: serde_json::Value =
+            serde_json::from_slice(&first_layer.to_bytes(Format::Json).unwrap()).unwrap();
+        json["state"]["first_layer"] = first_layer["state"].clone();
+        assert!(matches!(
+            Mnist::<f64>::from_bytes(&serde_json::to_vec(&json).unwrap()),
+            Err(ModelError::ArchitectureMismatch(_))
+        ));
+    }
+}
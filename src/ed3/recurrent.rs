@@ -0,0 +1,229 @@
+use super::{float::Float, layer::Amines, trainer::Model};
+use rand::Rng;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RecurrentConfig {
+    pub beta: f64,
+    pub alpha: f64,
+    /// Sigmoid temperature, the units output `1 / (1 + exp(-2u / u0))`.
+    pub u0: f64,
+    /// Number of times the units are updated per sample.
+    pub settle: usize,
+}
+
+impl Default for RecurrentConfig {
+    fn default() -> Self {
+        RecurrentConfig {
+            beta: 0.8,
+            alpha: 0.8,
+            u0: 0.4,
+            settle: 2,
+        }
+    }
+}
+
+/// The original ED topology: every unit reads the bias pair, the duplicated inputs and the
+/// outputs of all units, which are fed back while the network settles. The first unit is the
+/// output.
+#[derive(Debug, Clone)]
+pub struct Recurrent<F = f64> {
+    input: usize,
+    units: usize,
+    config: RecurrentConfig,
+    signs: Vec<F>,
+    // weights[k * width + m] connects source m to unit k
+    weights: Vec<F>,
+    last_state: Vec<F>,
+}
+
+impl<F: Float> Recurrent<F> {
+    pub fn new<R>(rng: &mut R, input: usize, units: usize) -> Self
+    where
+        R: Rng,
+    {
+        Self::with_config(rng, input, units, RecurrentConfig::default())
+    }
+
+    pub fn with_config<R>(rng: &mut R, input: usize, units: usize, config: RecurrentConfig) -> Self
+    where
+        R: Rng,
+    {
+        assert!(units > 0, "at least the output unit is required");
+        let width = 2 + input * 2 + units;
+        let signs: Vec<F> = (0..width)
+            .map(|m| if m % 2 == 0 { F::one() } else { -F::one() })
+            .collect();
+        let offset = 2 + input * 2;
+
+        let mut weights = Vec::with_capacity(units * width);
+        for k in 0..units {
+            for m in 0..width {
+                weights.push(F::cast(rng.gen::<f64>()) * signs[m] * signs[k + offset]);
+            }
+        }
+
+        Recurrent {
+            input,
+            units,
+            config,
+            signs,
+            weights,
+            last_state: Vec::new(),
+        }
+    }
+
+    pub fn input(&self) -> usize {
+        self.input
+    }
+
+    pub fn units(&self) -> usize {
+        self.units
+    }
+
+    pub fn weights(&self) -> &[F] {
+        &self.weights
+    }
+
+    fn width(&self) -> usize {
+        self.signs.len()
+    }
+
+    fn offset(&self) -> usize {
+        2 + self.input * 2
+    }
+
+    // The `sigmf` of the original code, evaluated exactly rather than through
+    // `Sigmoid::with_u0`, whose gain rounds differently.
+    fn sigmoid(&self, u: F) -> F {
+        F::one() / (F::one() + (F::cast(-2.) * u / F::cast(self.config.u0)).exp())
+    }
+
+    fn settle(&self, inputs: &[F]) -> Vec<F> {
+        assert_eq!(inputs.len(), self.input);
+        let width = self.width();
+        let offset = self.offset();
+
+        let mut state = vec![F::zero(); width];
+        state[0] = F::cast(self.config.beta);
+        state[1] = F::cast(self.config.beta);
+        for k in 0..self.input * 2 {
+            state[k + 2] = inputs[k / 2];
+        }
+
+        let mut outputs = vec![F::zero(); self.units];
+        for _ in 0..self.config.settle {
+            for (k, output) in outputs.iter_mut().enumerate() {
+                let weights = &self.weights[k * width..(k + 1) * width];
+                let u = weights.iter().zip(&state).map(|(&w, &x)| w * x).sum();
+                *output = self.sigmoid(u);
+            }
+            state[offset..].copy_from_slice(&outputs);
+        }
+        state
+    }
+
+    pub fn forward(&mut self, inputs: &[F]) -> F {
+        self.last_state = self.settle(inputs);
+        self.last_state[self.offset()]
+    }
+
+    pub fn forward_without_train(&self, inputs: &[F]) -> F {
+        self.settle(inputs)[self.offset()]
+    }
+
+    /// Takes either a signed `output - target` delta, as for the layered networks, or
+    /// `Amines`.
+    pub fn backward<E>(&mut self, error: E)
+    where
+        E: Into<Amines<F>>,
+    {
+        let width = self.width();
+        let offset = self.offset();
+        let error = error.into();
+        let amines = [error.positive, -error.negative];
+        let alpha = F::cast(self.config.alpha);
+
+        for k in 0..self.units {
+            let output = self.last_state[k + offset];
+            let tmp = output.abs() * (F::one() - output.abs());
+            let sign = self.signs[k + offset];
+            for m in 0..width {
+                let del = alpha * self.last_state[m] * tmp;
+                let w = &mut self.weights[k * width + m];
+                if self.signs[m] > F::zero() {
+                    *w += del * amines[0] * self.signs[m] * sign;
+                } else {
+                    *w -= del * amines[1] * self.signs[m] * sign;
+                }
+            }
+        }
+    }
+}
+
+impl<F: Float> Model<F> for Recurrent<F> {
+    type Output = F;
+
+    fn forward(&mut self, inputs: &[F]) -> F {
+        Recurrent::forward(self, inputs)
+    }
+    fn forward_without_train(&self, inputs: &[F]) -> F {
+        Recurrent::forward_without_train(self, inputs)
+    }
+    fn backward(&mut self, &delta: &F) {
+        Recurrent::backward(self, delta);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn test_xor() {
+        let mut rng = StdRng::from_seed([1; 32]);
+        let mut model = Recurrent::<f64>::new(&mut rng, 2, 5);
+        let train = [
+            ([0., 0.], 0.),
+            ([1., 0.], 1.),
+            ([0., 1.], 1.),
+            ([1., 1.], 0.),
+        ];
+
+        let converged = (0..2000).any(|_| {
+            let mut err = 0.;
+            for (inputs, target) in train.iter() {
+                let delta = model.forward(inputs) - target;
+                model.backward(delta);
+                err += delta.abs();
+            }
+            err < 0.1
+        });
+        assert!(converged);
+        for (inputs, target) in train.iter() {
+            assert!((model.forward_without_train(inputs) - target).abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn test_settle() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let config = RecurrentConfig {
+            settle: 1,
+            ..Default::default()
+        };
+        let model = Recurrent::with_config(&mut rng, 3, 4, config);
+        // With a single update, the units only see the bias and the inputs.
+        let width = model.width();
+        let u: f64 = model.weights[..model.offset()]
+            .iter()
+            .zip([0.8, 0.8, 0.1, 0.1, 0.5, 0.5, 0.9, 0.9])
+            .map(|(w, x)| w * x)
+            .sum();
+        assert_eq!(width, 12);
+        assert_eq!(
+            model.forward_without_train(&[0.1, 0.5, 0.9]),
+            model.sigmoid(u)
+        );
+    }
+}
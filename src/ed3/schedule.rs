@@ -0,0 +1,280 @@
+use std::{f64::consts::PI, fmt::Debug};
+
+/// A learning rate that changes during training. `step` is called after every epoch or every
+/// update, whichever the schedule is counted in. A model given one with `set_schedule` passes
+/// its rate to the layers, so it also reaches `RmsProp` and `Adam`, which normalize the deltas.
+pub trait Schedule: Send + Sync + Debug {
+    fn learning_rate(&self) -> f64;
+    /// `metric` is the monitored loss, only `ReduceOnPlateau` uses it.
+    fn step(&mut self, metric: Option<f64>);
+}
+
+// The optional schedule a model scales the deltas of its `backward` with, see the models'
+// `set_schedule`.
+#[derive(Debug, Default)]
+pub(super) struct ModelSchedule(Option<Box<dyn Schedule>>);
+
+impl ModelSchedule {
+    pub(super) fn set<S>(&mut self, schedule: S)
+    where
+        S: Schedule + 'static,
+    {
+        self.0 = Some(Box::new(schedule));
+    }
+
+    pub(super) fn learning_rate(&self) -> Option<f64> {
+        self.0.as_ref().map(|schedule| schedule.learning_rate())
+    }
+
+    pub(super) fn step(&mut self, metric: Option<f64>) {
+        if let Some(schedule) = &mut self.0 {
+            schedule.step(metric);
+        }
+    }
+
+    // The rate the layers scale the errors with, 1 without a schedule.
+    pub(super) fn rate(&self) -> f64 {
+        self.learning_rate().unwrap_or(1.)
+    }
+}
+
+impl Schedule for Box<dyn Schedule> {
+    fn learning_rate(&self) -> f64 {
+        self.as_ref().learning_rate()
+    }
+    fn step(&mut self, metric: Option<f64>) {
+        self.as_mut().step(metric);
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Constant {
+    pub learning_rate: f64,
+}
+impl Constant {
+    pub fn new(learning_rate: f64) -> Self {
+        Constant { learning_rate }
+    }
+}
+impl Schedule for Constant {
+    fn learning_rate(&self) -> f64 {
+        self.learning_rate
+    }
+    fn step(&mut self, _: Option<f64>) {}
+}
+
+/// Multiplies the rate by `gamma` every `step_size` steps.
+#[derive(Debug, Clone, Copy)]
+pub struct StepDecay {
+    pub initial: f64,
+    pub gamma: f64,
+    pub step_size: usize,
+    t: usize,
+}
+impl StepDecay {
+    pub fn new(initial: f64, gamma: f64, step_size: usize) -> Self {
+        assert!(step_size > 0);
+        StepDecay {
+            initial,
+            gamma,
+            step_size,
+            t: 0,
+        }
+    }
+}
+impl Schedule for StepDecay {
+    fn learning_rate(&self) -> f64 {
+        self.initial * self.gamma.powi((self.t / self.step_size) as i32)
+    }
+    fn step(&mut self, _: Option<f64>) {
+        self.t += 1;
+    }
+}
+
+/// Multiplies the rate by `gamma` every step.
+#[derive(Debug, Clone, Copy)]
+pub struct Exponential {
+    pub initial: f64,
+    pub gamma: f64,
+    t: usize,
+}
+impl Exponential {
+    pub fn new(initial: f64, gamma: f64) -> Self {
+        Exponential {
+            initial,
+            gamma,
+            t: 0,
+        }
+    }
+}
+impl Schedule for Exponential {
+    fn learning_rate(&self) -> f64 {
+        self.initial * self.gamma.powi(self.t as i32)
+    }
+    fn step(&mut self, _: Option<f64>) {
+        self.t += 1;
+    }
+}
+
+/// Anneals from `initial` to `min` along half a cosine over `period` steps, then stays at `min`.
+#[derive(Debug, Clone, Copy)]
+pub struct Cosine {
+    pub initial: f64,
+    pub min: f64,
+    pub period: usize,
+    t: usize,
+}
+impl Cosine {
+    pub fn new(initial: f64, min: f64, period: usize) -> Self {
+        assert!(period > 0);
+        Cosine {
+            initial,
+            min,
+            period,
+            t: 0,
+        }
+    }
+}
+impl Schedule for Cosine {
+    fn learning_rate(&self) -> f64 {
+        let progress = self.t.min(self.period) as f64 / self.period as f64;
+        self.min + (self.initial - self.min) * (1. + (PI * progress).cos()) / 2.
+    }
+    fn step(&mut self, _: Option<f64>) {
+        self.t += 1;
+    }
+}
+
+/// Ramps linearly up to the rate of `schedule` over the first `steps` steps, then follows it.
+#[derive(Debug, Clone, Copy)]
+pub struct Warmup<S> {
+    pub steps: usize,
+    pub schedule: S,
+    t: usize,
+}
+impl<S> Warmup<S>
+where
+    S: Schedule,
+{
+    pub fn new(steps: usize, schedule: S) -> Self {
+        Warmup {
+            steps,
+            schedule,
+            t: 0,
+        }
+    }
+}
+impl<S> Schedule for Warmup<S>
+where
+    S: Schedule,
+{
+    fn learning_rate(&self) -> f64 {
+        let learning_rate = self.schedule.learning_rate();
+        if self.t < self.steps {
+            learning_rate * (self.t + 1) as f64 / (self.steps + 1) as f64
+        } else {
+            learning_rate
+        }
+    }
+    fn step(&mut self, metric: Option<f64>) {
+        if self.t < self.steps {
+            self.t += 1;
+        } else {
+            self.schedule.step(metric);
+        }
+    }
+}
+
+/// Multiplies the rate by `factor`, down to `min`, once the metric has not improved by more
+/// than `threshold` for more than `patience` steps.
+#[derive(Debug, Clone, Copy)]
+pub struct ReduceOnPlateau {
+    pub factor: f64,
+    pub patience: usize,
+    pub threshold: f64,
+    pub min: f64,
+    learning_rate: f64,
+    best: f64,
+    bad_steps: usize,
+}
+impl ReduceOnPlateau {
+    pub fn new(initial: f64, factor: f64, patience: usize) -> Self {
+        ReduceOnPlateau {
+            factor,
+            patience,
+            threshold: 0.,
+            min: 0.,
+            learning_rate: initial,
+            best: f64::INFINITY,
+            bad_steps: 0,
+        }
+    }
+}
+impl Schedule for ReduceOnPlateau {
+    fn learning_rate(&self) -> f64 {
+        self.learning_rate
+    }
+    fn step(&mut self, metric: Option<f64>) {
+        let metric = metric.expect("ReduceOnPlateau needs the monitored metric");
+        if metric < self.best - self.threshold {
+            self.best = metric;
+            self.bad_steps = 0;
+            return;
+        }
+        self.bad_steps += 1;
+        if self.bad_steps > self.patience {
+            self.learning_rate = (self.learning_rate * self.factor).max(self.min);
+            self.bad_steps = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rates<S: Schedule>(mut schedule: S, metrics: &[f64]) -> Vec<f64> {
+        metrics
+            .iter()
+            .map(|&metric| {
+                let learning_rate = schedule.learning_rate();
+                schedule.step(Some(metric));
+                learning_rate
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_decays() {
+        let metrics = [0.; 5];
+        assert_eq!(
+            rates(StepDecay::new(1., 0.5, 2), &metrics),
+            [1., 1., 0.5, 0.5, 0.25]
+        );
+        assert_eq!(
+            rates(Exponential::new(1., 0.5), &metrics),
+            [1., 0.5, 0.25, 0.125, 0.0625]
+        );
+        let cosine = rates(Cosine::new(1., 0.2, 2), &metrics);
+        assert_eq!(cosine[0], 1.);
+        assert!((cosine[1] - 0.6).abs() < 1e-12);
+        assert_eq!(&cosine[2..], [0.2; 3]);
+    }
+
+    #[test]
+    fn test_warmup() {
+        let warmup = Warmup::new(3, StepDecay::new(1., 0.5, 1));
+        assert_eq!(rates(warmup, &[0.; 5]), [0.25, 0.5, 0.75, 1., 0.5]);
+    }
+
+    #[test]
+    fn test_reduce_on_plateau() {
+        let mut schedule = ReduceOnPlateau::new(1., 0.5, 1);
+        schedule.min = 0.3;
+        let metrics = [3., 2., 2., 2., 1., 1., 1., 1., 1.];
+        assert_eq!(
+            rates(schedule, &metrics),
+            [1., 1., 1., 1., 0.5, 0.5, 0.5, 0.3, 0.3]
+        );
+    }
+}
@@ -0,0 +1,916 @@
+use super::{
+    alignment::LayerAlignment,
+    differentiable_fn::DifferentiableFn,
+    float::Float,
+    layer::{Amines, BatchReduction, Layer, LayerConfig, Layout, MultiOutputLayer},
+    optimizer::Optimizer,
+    schedule::{ModelSchedule, Schedule},
+    trainer::Model,
+    util::duplicate_elements,
+};
+use rand::Rng;
+
+pub trait Module<F = f64>: Send {
+    fn forward(&mut self, inputs: Vec<F>) -> Vec<F>;
+    fn forward_without_train(&self, inputs: Vec<F>) -> Vec<F>;
+    /// `learning_rate` scales `error`, see `Layer::backward_with_rate`.
+    fn backward(&mut self, error: Amines<F>, learning_rate: f64);
+    fn forward_batch(&mut self, inputs: Vec<Vec<F>>) -> Vec<Vec<F>>;
+    fn backward_batch(
+        &mut self,
+        errors: &[Amines<F>],
+        reduction: BatchReduction,
+        learning_rate: f64,
+    );
+    fn sign_violations(&self) -> usize {
+        0
+    }
+    fn gradient_alignment(
+        &self,
+        inputs: &[F],
+        error: Amines<F>,
+        output_gradient: &[F],
+    ) -> (LayerAlignment, Vec<F>);
+}
+
+impl<ActivationFunc, F> Module<F> for Layer<ActivationFunc, F>
+where
+    ActivationFunc: DifferentiableFn<F, Args = F>,
+    F: Float,
+{
+    fn forward(&mut self, inputs: Vec<F>) -> Vec<F> {
+        Layer::forward(self, inputs)
+    }
+    fn forward_without_train(&self, inputs: Vec<F>) -> Vec<F> {
+        Layer::forward_without_train(self, inputs)
+    }
+    fn backward(&mut self, error: Amines<F>, learning_rate: f64) {
+        Layer::backward_with_rate(self, error, learning_rate);
+    }
+    fn forward_batch(&mut self, inputs: Vec<Vec<F>>) -> Vec<Vec<F>> {
+        Layer::forward_batch(self, inputs)
+    }
+    fn backward_batch(
+        &mut self,
+        errors: &[Amines<F>],
+        reduction: BatchReduction,
+        learning_rate: f64,
+    ) {
+        Layer::backward_batch_with_rate(self, errors, reduction, learning_rate);
+    }
+    fn sign_violations(&self) -> usize {
+        Layer::sign_violations(self)
+    }
+    fn gradient_alignment(
+        &self,
+        inputs: &[F],
+        error: Amines<F>,
+        output_gradient: &[F],
+    ) -> (LayerAlignment, Vec<F>) {
+        Layer::gradient_alignment(self, inputs, error, output_gradient)
+    }
+}
+
+pub trait MultiOutputModule<F = f64>: Send {
+    fn forward(&mut self, inputs: Vec<Vec<F>>) -> Vec<Vec<F>>;
+    fn forward_without_train(&self, inputs: Vec<Vec<F>>) -> Vec<Vec<F>>;
+    /// `learning_rate` scales `errors`, see `MultiOutputLayer::backward_with_rate`.
+    fn backward(&mut self, errors: &[Amines<F>], learning_rate: f64);
+    fn forward_batch(&mut self, inputs: Vec<Vec<Vec<F>>>) -> Vec<Vec<Vec<F>>>;
+    fn backward_batch(
+        &mut self,
+        errors: &[Vec<Amines<F>>],
+        reduction: BatchReduction,
+        learning_rate: f64,
+    );
+    fn sign_violations(&self) -> usize {
+        0
+    }
+    fn gradient_alignment(
+        &self,
+        inputs: &[Vec<F>],
+        errors: &[Amines<F>],
+        output_gradients: &[Vec<F>],
+    ) -> (Vec<LayerAlignment>, Vec<Vec<F>>);
+}
+
+impl<ActivationFunc, F> MultiOutputModule<F> for MultiOutputLayer<ActivationFunc, F>
+where
+    ActivationFunc: DifferentiableFn<F, Args = F>,
+    F: Float,
+{
+    fn forward(&mut self, inputs: Vec<Vec<F>>) -> Vec<Vec<F>> {
+        MultiOutputLayer::forward(self, inputs)
+    }
+    fn forward_without_train(&self, inputs: Vec<Vec<F>>) -> Vec<Vec<F>> {
+        MultiOutputLayer::forward_without_train(self, inputs)
+    }
+    fn backward(&mut self, errors: &[Amines<F>], learning_rate: f64) {
+        MultiOutputLayer::backward_with_rate(self, errors, learning_rate);
+    }
+    fn forward_batch(&mut self, inputs: Vec<Vec<Vec<F>>>) -> Vec<Vec<Vec<F>>> {
+        MultiOutputLayer::forward_batch(self, inputs)
+    }
+    fn backward_batch(
+        &mut self,
+        errors: &[Vec<Amines<F>>],
+        reduction: BatchReduction,
+        learning_rate: f64,
+    ) {
+        MultiOutputLayer::backward_batch_with_rate(self, errors, reduction, learning_rate);
+    }
+    fn sign_violations(&self) -> usize {
+        MultiOutputLayer::sign_violations(self)
+    }
+    fn gradient_alignment(
+        &self,
+        inputs: &[Vec<F>],
+        errors: &[Amines<F>],
+        output_gradients: &[Vec<F>],
+    ) -> (Vec<LayerAlignment>, Vec<Vec<F>>) {
+        MultiOutputLayer::gradient_alignment(self, inputs, errors, output_gradients)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoder {
+    #[default]
+    Identity,
+    /// Feeds every input twice, to an excitatory and an inhibitory input neuron.
+    Duplicate,
+}
+
+impl Encoder {
+    pub fn encode<F: Float>(&self, inputs: &[F]) -> Vec<F> {
+        match self {
+            Encoder::Identity => inputs.to_vec(),
+            Encoder::Duplicate => duplicate_elements(inputs.iter()).collect(),
+        }
+    }
+
+    pub fn width(&self, input: usize) -> usize {
+        match self {
+            Encoder::Identity => input,
+            Encoder::Duplicate => input * 2,
+        }
+    }
+
+    // The duplicated inputs only form excitatory/inhibitory pairs under `Layout::Parity`.
+    fn check_layout(&self, layout: Layout) {
+        assert!(
+            *self != Encoder::Duplicate || layout == Layout::Parity,
+            "Encoder::Duplicate needs Layout::Parity in the first layer"
+        );
+    }
+}
+
+/// `Module`s applied in order to the encoded inputs.
+pub struct Sequential<F = f64> {
+    encoder: Encoder,
+    layers: Vec<Box<dyn Module<F>>>,
+    schedule: ModelSchedule,
+}
+
+impl<F: Float> Sequential<F> {
+    pub fn builder<R>(rng: R, input: usize) -> SequentialBuilder<R, F>
+    where
+        R: Rng,
+    {
+        SequentialBuilder {
+            rng,
+            encoder: Encoder::default(),
+            config: LayerConfig::default(),
+            optimizer: None,
+            input,
+            width: input,
+            layers: Vec::new(),
+        }
+    }
+
+    /// The errors passed to `backward` are scaled by the rate of `schedule`, which the caller
+    /// advances with `step_schedule`.
+    pub fn set_schedule<S>(&mut self, schedule: S)
+    where
+        S: Schedule + 'static,
+    {
+        self.schedule.set(schedule);
+    }
+
+    pub fn learning_rate(&self) -> Option<f64> {
+        self.schedule.learning_rate()
+    }
+
+    /// The sign violations of every layer, see `Layer::sign_violations`.
+    pub fn sign_violations(&self) -> Vec<usize> {
+        self.layers
+            .iter()
+            .map(|layer| layer.sign_violations())
+            .collect()
+    }
+
+    pub fn step_schedule(&mut self, metric: Option<f64>) {
+        self.schedule.step(metric);
+    }
+
+    pub fn forward(&mut self, inputs: &[F]) -> Vec<F> {
+        let x = self.encoder.encode(inputs);
+        self.layers.iter_mut().fold(x, |x, layer| layer.forward(x))
+    }
+
+    pub fn forward_without_train(&self, inputs: &[F]) -> Vec<F> {
+        let x = self.encoder.encode(inputs);
+        self.layers
+            .iter()
+            .fold(x, |x, layer| layer.forward_without_train(x))
+    }
+
+    pub fn backward<E>(&mut self, error: E)
+    where
+        E: Into<Amines<F>>,
+    {
+        let (error, rate) = (error.into(), self.schedule.rate());
+        self.layers
+            .iter_mut()
+            .for_each(|layer| layer.backward(error, rate));
+    }
+
+    /// Compares the update `backward` would make for one sample with backprop, layer by layer.
+    /// Like in `backward`, the error is the derivative of the loss with respect to every output.
+    pub fn gradient_alignment<E>(&self, inputs: &[F], error: E) -> Vec<LayerAlignment>
+    where
+        E: Into<Amines<F>>,
+    {
+        let error = error.into();
+        let mut layer_inputs = vec![self.encoder.encode(inputs)];
+        for layer in self.layers.iter() {
+            let x = layer.forward_without_train(layer_inputs.last().unwrap().clone());
+            layer_inputs.push(x);
+        }
+
+        let mut gradient = vec![error.delta(); layer_inputs.pop().unwrap().len()];
+        let mut alignments: Vec<_> = self
+            .layers
+            .iter()
+            .zip(layer_inputs.iter())
+            .rev()
+            .map(|(layer, inputs)| {
+                let (alignment, input_gradient) =
+                    layer.gradient_alignment(inputs, error, &gradient);
+                gradient = input_gradient;
+                alignment
+            })
+            .collect();
+        alignments.reverse();
+        alignments
+    }
+
+    pub fn forward_batch(&mut self, inputs: &[&[F]]) -> Vec<Vec<F>> {
+        let x = inputs
+            .iter()
+            .map(|inputs| self.encoder.encode(inputs))
+            .collect();
+        self.layers
+            .iter_mut()
+            .fold(x, |x, layer| layer.forward_batch(x))
+    }
+
+    pub fn backward_batch<E>(&mut self, errors: &[E], reduction: BatchReduction)
+    where
+        E: Into<Amines<F>> + Copy,
+    {
+        let errors: Vec<_> = errors.iter().map(|&error| error.into()).collect();
+        let rate = self.schedule.rate();
+        self.layers
+            .iter_mut()
+            .for_each(|layer| layer.backward_batch(&errors, reduction, rate));
+    }
+}
+
+type NewOptimizer<F> = dyn Fn() -> Box<dyn Optimizer<F>>;
+
+pub struct SequentialBuilder<R, F = f64>
+where
+    R: Rng,
+{
+    rng: R,
+    encoder: Encoder,
+    config: LayerConfig,
+    optimizer: Option<Box<NewOptimizer<F>>>,
+    input: usize,
+    width: usize,
+    layers: Vec<Box<dyn Module<F>>>,
+}
+
+impl<R, F> SequentialBuilder<R, F>
+where
+    R: Rng,
+    F: Float,
+{
+    pub fn encoder(mut self, encoder: Encoder) -> Self {
+        assert!(self.layers.is_empty(), "set the encoder before the layers");
+        self.encoder = encoder;
+        self.width = encoder.width(self.input);
+        self
+    }
+
+    /// Config of the layers added after this call.
+    pub fn config(mut self, config: LayerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Every layer added after this call gets its own copy of `optimizer`.
+    pub fn optimizer<O>(mut self, optimizer: O) -> Self
+    where
+        O: Optimizer<F> + Clone + 'static,
+    {
+        self.optimizer = Some(Box::new(move || Box::new(optimizer.clone())));
+        self
+    }
+
+    pub fn layer<ActivationFunc>(mut self, activation: ActivationFunc, output: usize) -> Self
+    where
+        ActivationFunc: DifferentiableFn<F, Args = F> + 'static,
+    {
+        if self.layers.is_empty() {
+            self.encoder.check_layout(self.config.layout);
+        }
+        let config = LayerConfig {
+            sign_constraint: self.config.sign_constraint.for_layer(self.layers.len()),
+            ..self.config
+        };
+        let mut layer = Layer::with_config(&mut self.rng, self.width, output, activation, config);
+        if let Some(new_optimizer) = &self.optimizer {
+            layer.set_optimizer_with(new_optimizer);
+        }
+        self.layers.push(Box::new(layer));
+        self.width = output;
+        self
+    }
+
+    pub fn output_layer<ActivationFunc>(
+        self,
+        activation: ActivationFunc,
+        output: usize,
+    ) -> Sequential<F>
+    where
+        ActivationFunc: DifferentiableFn<F, Args = F> + 'static,
+    {
+        let builder = self.layer(activation, output);
+        Sequential {
+            encoder: builder.encoder,
+            layers: builder.layers,
+            schedule: ModelSchedule::default(),
+        }
+    }
+}
+
+/// Independent sub-networks of the same architecture, one per output, built from
+/// `MultiOutputLayer`s. They share their inputs unless built with `builder_with_inputs`.
+pub struct MultiOutputSequential<F = f64> {
+    encoder: Encoder,
+    last_output: usize,
+    layers: Vec<Box<dyn MultiOutputModule<F>>>,
+    schedule: ModelSchedule,
+}
+
+impl<F: Float> MultiOutputSequential<F> {
+    pub fn builder<R>(
+        rng: R,
+        last_output: usize,
+        input: usize,
+    ) -> MultiOutputSequentialBuilder<R, F>
+    where
+        R: Rng,
+    {
+        Self::builder_with_inputs(rng, &vec![input; last_output])
+    }
+
+    /// Sub-network `i` takes `inputs[i]` inputs, see `MultiOutputLayer::with_inputs`. Feed the
+    /// model with `forward_each`.
+    pub fn builder_with_inputs<R>(rng: R, inputs: &[usize]) -> MultiOutputSequentialBuilder<R, F>
+    where
+        R: Rng,
+    {
+        MultiOutputSequentialBuilder {
+            rng,
+            encoder: Encoder::default(),
+            config: LayerConfig::default(),
+            optimizer: None,
+            last_output: inputs.len(),
+            inputs: inputs.to_vec(),
+            widths: inputs.to_vec(),
+            parallel: false,
+            layers: Vec::new(),
+        }
+    }
+
+    fn encode(&self, inputs: &[&[F]]) -> Vec<Vec<F>> {
+        assert_eq!(inputs.len(), self.last_output, "one input per sub-network");
+        inputs
+            .iter()
+            .map(|inputs| self.encoder.encode(inputs))
+            .collect()
+    }
+
+    /// The errors passed to `backward` are scaled by the rate of `schedule`, which the caller
+    /// advances with `step_schedule`.
+    pub fn set_schedule<S>(&mut self, schedule: S)
+    where
+        S: Schedule + 'static,
+    {
+        self.schedule.set(schedule);
+    }
+
+    pub fn learning_rate(&self) -> Option<f64> {
+        self.schedule.learning_rate()
+    }
+
+    /// The sign violations of every layer, see `Layer::sign_violations`.
+    pub fn sign_violations(&self) -> Vec<usize> {
+        self.layers
+            .iter()
+            .map(|layer| layer.sign_violations())
+            .collect()
+    }
+
+    pub fn step_schedule(&mut self, metric: Option<f64>) {
+        self.schedule.step(metric);
+    }
+
+    /// Feeds `inputs` to every sub-network.
+    pub fn forward(&mut self, inputs: &[F]) -> Vec<F> {
+        self.forward_each(&vec![inputs; self.last_output])
+    }
+
+    pub fn forward_without_train(&self, inputs: &[F]) -> Vec<F> {
+        self.forward_each_without_train(&vec![inputs; self.last_output])
+    }
+
+    /// Feeds `inputs[i]` to sub-network `i`.
+    pub fn forward_each(&mut self, inputs: &[&[F]]) -> Vec<F> {
+        let x = self.encode(inputs);
+        let x = self.layers.iter_mut().fold(x, |x, layer| layer.forward(x));
+        x.into_iter().map(|x| x[0]).collect()
+    }
+
+    pub fn forward_each_without_train(&self, inputs: &[&[F]]) -> Vec<F> {
+        let x = self.encode(inputs);
+        let x = self
+            .layers
+            .iter()
+            .fold(x, |x, layer| layer.forward_without_train(x));
+        x.into_iter().map(|x| x[0]).collect()
+    }
+
+    pub fn backward<E>(&mut self, errors: &[E])
+    where
+        E: Into<Amines<F>> + Copy,
+    {
+        let errors: Vec<_> = errors.iter().map(|&error| error.into()).collect();
+        let rate = self.schedule.rate();
+        self.layers
+            .iter_mut()
+            .for_each(|layer| layer.backward(&errors, rate));
+    }
+
+    /// `Sequential::gradient_alignment` of every sub-network, `alignments[layer][output]`.
+    pub fn gradient_alignment<E>(&self, inputs: &[F], errors: &[E]) -> Vec<Vec<LayerAlignment>>
+    where
+        E: Into<Amines<F>> + Copy,
+    {
+        self.gradient_alignment_each(&vec![inputs; self.last_output], errors)
+    }
+
+    /// Like `gradient_alignment`, feeding `inputs[i]` to sub-network `i`.
+    pub fn gradient_alignment_each<E>(
+        &self,
+        inputs: &[&[F]],
+        errors: &[E],
+    ) -> Vec<Vec<LayerAlignment>>
+    where
+        E: Into<Amines<F>> + Copy,
+    {
+        let errors: Vec<Amines<F>> = errors.iter().map(|&error| error.into()).collect();
+        let mut layer_inputs = vec![self.encode(inputs)];
+        for layer in self.layers.iter() {
+            let x = layer.forward_without_train(layer_inputs.last().unwrap().clone());
+            layer_inputs.push(x);
+        }
+
+        let mut gradients: Vec<_> = layer_inputs
+            .pop()
+            .unwrap()
+            .iter()
+            .zip(&errors)
+            .map(|(outputs, error)| vec![error.delta(); outputs.len()])
+            .collect();
+        let mut alignments: Vec<_> = self
+            .layers
+            .iter()
+            .zip(layer_inputs.iter())
+            .rev()
+            .map(|(layer, inputs)| {
+                let (alignments, input_gradients) =
+                    layer.gradient_alignment(inputs, &errors, &gradients);
+                gradients = input_gradients;
+                alignments
+            })
+            .collect();
+        alignments.reverse();
+        alignments
+    }
+
+    /// Feeds every sample to every sub-network.
+    pub fn forward_batch(&mut self, inputs: &[&[F]]) -> Vec<Vec<F>> {
+        let inputs: Vec<_> = inputs
+            .iter()
+            .map(|&inputs| vec![inputs; self.last_output])
+            .collect();
+        let inputs: Vec<_> = inputs.iter().map(|inputs| &inputs[..]).collect();
+        self.forward_batch_each(&inputs)
+    }
+
+    /// Runs a mini-batch, `inputs[sample][output]`, feeding `inputs[sample][i]` to sub-network
+    /// `i`.
+    pub fn forward_batch_each(&mut self, inputs: &[&[&[F]]]) -> Vec<Vec<F>> {
+        let x = inputs.iter().map(|inputs| self.encode(inputs)).collect();
+        let x = self
+            .layers
+            .iter_mut()
+            .fold(x, |x, layer| layer.forward_batch(x));
+        x.into_iter()
+            .map(|x| x.into_iter().map(|x| x[0]).collect())
+            .collect()
+    }
+
+    pub fn backward_batch<E>(&mut self, errors: &[Vec<E>], reduction: BatchReduction)
+    where
+        E: Into<Amines<F>> + Copy,
+    {
+        let errors: Vec<Vec<_>> = errors
+            .iter()
+            .map(|errors| errors.iter().map(|&error| error.into()).collect())
+            .collect();
+        let rate = self.schedule.rate();
+        self.layers
+            .iter_mut()
+            .for_each(|layer| layer.backward_batch(&errors, reduction, rate));
+    }
+}
+
+pub struct MultiOutputSequentialBuilder<R, F = f64>
+where
+    R: Rng,
+{
+    rng: R,
+    encoder: Encoder,
+    config: LayerConfig,
+    optimizer: Option<Box<NewOptimizer<F>>>,
+    last_output: usize,
+    inputs: Vec<usize>,
+    widths: Vec<usize>,
+    parallel: bool,
+    layers: Vec<Box<dyn MultiOutputModule<F>>>,
+}
+
+impl<R, F> MultiOutputSequentialBuilder<R, F>
+where
+    R: Rng,
+    F: Float,
+{
+    pub fn encoder(mut self, encoder: Encoder) -> Self {
+        assert!(self.layers.is_empty(), "set the encoder before the layers");
+        self.encoder = encoder;
+        self.widths = self
+            .inputs
+            .iter()
+            .map(|&input| encoder.width(input))
+            .collect();
+        self
+    }
+
+    /// Config of the layers added after this call.
+    pub fn config(mut self, config: LayerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Every layer added after this call gets its own copy of `optimizer`.
+    pub fn optimizer<O>(mut self, optimizer: O) -> Self
+    where
+        O: Optimizer<F> + Clone + 'static,
+    {
+        self.optimizer = Some(Box::new(move || Box::new(optimizer.clone())));
+        self
+    }
+
+    /// See `MultiOutputLayer::set_parallel`, applies to the layers added after this call.
+    pub fn parallel(mut self, parallel: bool) -> Self {
+        self.parallel = parallel;
+        self
+    }
+
+    pub fn layer<ActivationFunc>(mut self, activation: ActivationFunc, output: usize) -> Self
+    where
+        ActivationFunc: DifferentiableFn<F, Args = F> + 'static,
+    {
+        if self.layers.is_empty() {
+            self.encoder.check_layout(self.config.layout);
+        }
+        let config = LayerConfig {
+            sign_constraint: self.config.sign_constraint.for_layer(self.layers.len()),
+            ..self.config
+        };
+        let mut layer =
+            MultiOutputLayer::with_inputs(&mut self.rng, &self.widths, output, activation, config);
+        layer.set_parallel(self.parallel);
+        if let Some(new_optimizer) = &self.optimizer {
+            layer.set_optimizer_with(new_optimizer);
+        }
+        self.layers.push(Box::new(layer));
+        self.widths = vec![output; self.last_output];
+        self
+    }
+
+    /// Adds the last layer, with a single output per sub-network.
+    pub fn output_layer<ActivationFunc>(
+        self,
+        activation: ActivationFunc,
+    ) -> MultiOutputSequential<F>
+    where
+        ActivationFunc: DifferentiableFn<F, Args = F> + 'static,
+    {
+        let builder = self.layer(activation, 1);
+        MultiOutputSequential {
+            encoder: builder.encoder,
+            last_output: builder.last_output,
+            layers: builder.layers,
+            schedule: ModelSchedule::default(),
+        }
+    }
+}
+
+/// Trains the single output of the network.
+impl<F: Float> Model<F> for Sequential<F> {
+    type Output = F;
+
+    fn forward(&mut self, inputs: &[F]) -> F {
+        let output = Sequential::forward(self, inputs);
+        assert_eq!(output.len(), 1, "Model needs a single output");
+        output[0]
+    }
+    fn forward_without_train(&self, inputs: &[F]) -> F {
+        Sequential::forward_without_train(self, inputs)[0]
+    }
+    fn backward(&mut self, &delta: &F) {
+        Sequential::backward(self, delta);
+    }
+    fn learning_rate(&self) -> Option<f64> {
+        Sequential::learning_rate(self)
+    }
+    fn step_schedule(&mut self, metric: Option<f64>) {
+        Sequential::step_schedule(self, metric);
+    }
+}
+
+impl<F: Float> Model<F> for MultiOutputSequential<F> {
+    type Output = Vec<F>;
+
+    fn forward(&mut self, inputs: &[F]) -> Vec<F> {
+        MultiOutputSequential::forward(self, inputs)
+    }
+    fn forward_without_train(&self, inputs: &[F]) -> Vec<F> {
+        MultiOutputSequential::forward_without_train(self, inputs)
+    }
+    fn backward(&mut self, deltas: &Vec<F>) {
+        MultiOutputSequential::backward(self, deltas);
+    }
+    fn learning_rate(&self) -> Option<f64> {
+        MultiOutputSequential::learning_rate(self)
+    }
+    fn step_schedule(&mut self, metric: Option<f64>) {
+        MultiOutputSequential::step_schedule(self, metric);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        differentiable_fn::{PassThrough, Sigmoid},
+        gate::Gate,
+        mnist::Mnist,
+    };
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    #[should_panic(expected = "Encoder::Duplicate needs Layout::Parity")]
+    fn test_duplicate_layout() {
+        let config = LayerConfig {
+            layout: Layout::Blocks {
+                excitatory_fraction: 0.5,
+            },
+            ..Default::default()
+        };
+        MultiOutputSequential::<f64>::builder(StdRng::seed_from_u64(42), 2, 2)
+            .encoder(Encoder::Duplicate)
+            .config(config)
+            .output_layer(Sigmoid::default());
+    }
+
+    #[test]
+    fn test_same_as_gate() {
+        let mut gate = Gate::<Sigmoid>::new();
+        let mut model = Sequential::builder(StdRng::seed_from_u64(42), 2)
+            .encoder(Encoder::Duplicate)
+            .layer(Sigmoid::default(), 8)
+            .layer(Sigmoid::default(), 8)
+            .output_layer(Sigmoid::default(), 1);
+
+        for (i, inputs) in [[0., 0.], [0., 1.], [1., 0.], [1., 1.]].iter().enumerate() {
+            let output = gate.forward(inputs);
+            assert_eq!(model.forward(inputs), vec![output]);
+            let delta = output - (i % 3 != 0) as u8 as f64;
+            gate.backward(delta);
+            model.backward(delta);
+        }
+        assert_eq!(
+            model.forward_without_train(&[1., 0.]),
+            vec![gate.forward(&[1., 0.])]
+        );
+    }
+
+    #[test]
+    fn test_same_as_mnist() {
+        same_as_mnist::<f64>();
+        same_as_mnist::<f32>();
+    }
+
+    fn same_as_mnist<F: Float>() {
+        let mut mnist = Mnist::<F>::new(2, 4);
+        let mut model = Sequential::<F>::builder(StdRng::seed_from_u64(42), 784)
+            .encoder(Encoder::Duplicate)
+            .layer(Sigmoid::default(), 4)
+            .layer(Sigmoid::default(), 4)
+            .layer(Sigmoid::default(), 4)
+            .output_layer(PassThrough, 1);
+
+        let images: Vec<Vec<_>> = (0..3)
+            .map(|i| {
+                (0..784)
+                    .map(|j| F::cast(((i + j) % 5) as f64 / 4.))
+                    .collect()
+            })
+            .collect();
+        let images: Vec<_> = images.iter().map(|image| &image[..]).collect();
+        let outputs = mnist.forward_batch(&images);
+        let model_outputs: Vec<_> = model
+            .forward_batch(&images)
+            .into_iter()
+            .map(|x| x[0])
+            .collect();
+        assert_eq!(model_outputs, outputs);
+
+        mnist.backward_batch(&outputs, BatchReduction::Mean);
+        model.backward_batch(&outputs, BatchReduction::Mean);
+        assert_eq!(
+            model.forward_without_train(images[0]),
+            vec![mnist.forward_without_train(images[0])]
+        );
+    }
+
+    #[test]
+    fn test_gradient_alignment() {
+        // A single layer with duplicated inputs: ED only moves the weights of one parity, which
+        // match backprop exactly.
+        let model = Sequential::builder(StdRng::seed_from_u64(42), 2)
+            .encoder(Encoder::Duplicate)
+            .output_layer(PassThrough, 1);
+        let alignments = model.gradient_alignment(&[0.3, 0.8], -0.5);
+        assert_eq!(alignments.len(), 1);
+        assert!((alignments[0].layer.cosine_similarity - 0.5f64.sqrt()).abs() < 1e-12);
+        assert_eq!(alignments[0].layer.sign_agreement, 0.5);
+        assert_eq!(alignments[0].neurons, vec![alignments[0].layer]);
+
+        let model = Sequential::builder(StdRng::seed_from_u64(42), 2)
+            .encoder(Encoder::Duplicate)
+            .config(LayerConfig {
+                bias: Some(0.8),
+                ..Default::default()
+            })
+            .layer(Sigmoid::default(), 8)
+            .output_layer(Sigmoid::default(), 1);
+        let before = model.forward_without_train(&[0.3, 0.8]);
+        let alignments = model.gradient_alignment(&[0.3, 0.8], 0.5);
+        let neurons: Vec<_> = alignments.iter().map(|layer| layer.neurons.len()).collect();
+        assert_eq!(neurons, vec![8, 1]);
+        for alignment in alignments.iter().flat_map(|layer| &layer.neurons) {
+            assert!((-1. ..=1.).contains(&alignment.cosine_similarity));
+            assert!((0. ..=1.).contains(&alignment.sign_agreement));
+        }
+        assert_eq!(model.forward_without_train(&[0.3, 0.8]), before);
+    }
+
+    #[test]
+    fn test_gradient_alignment_of_models() {
+        // Gate, Mnist and a single-output MultiOutputSequential match their Sequential twins.
+        let gate = Gate::<Sigmoid>::new();
+        let model = Sequential::builder(StdRng::seed_from_u64(42), 2)
+            .encoder(Encoder::Duplicate)
+            .layer(Sigmoid::default(), 8)
+            .layer(Sigmoid::default(), 8)
+            .output_layer(Sigmoid::default(), 1);
+        assert_eq!(
+            gate.gradient_alignment(&[1., 0.], 0.3),
+            model.gradient_alignment(&[1., 0.], 0.3)
+        );
+
+        let mnist = Mnist::new(1, 4);
+        let model = Sequential::builder(StdRng::seed_from_u64(42), 784)
+            .encoder(Encoder::Duplicate)
+            .layer(Sigmoid::default(), 4)
+            .layer(Sigmoid::default(), 4)
+            .output_layer(PassThrough, 1);
+        let image: Vec<_> = (0..784).map(|i| (i % 5) as f64 / 4.).collect();
+        let alignments = mnist.gradient_alignment(&image, -0.4);
+        assert_eq!(alignments.len(), 3);
+        assert_eq!(alignments, model.gradient_alignment(&image, -0.4));
+
+        let multi_output = MultiOutputSequential::builder(StdRng::seed_from_u64(42), 1, 2)
+            .encoder(Encoder::Duplicate)
+            .layer(Sigmoid::default(), 8)
+            .output_layer(Sigmoid::default());
+        let model = Sequential::builder(StdRng::seed_from_u64(42), 2)
+            .encoder(Encoder::Duplicate)
+            .layer(Sigmoid::default(), 8)
+            .output_layer(Sigmoid::default(), 1);
+        let alignments: Vec<_> = multi_output
+            .gradient_alignment(&[0.3, 0.8], &[0.5])
+            .into_iter()
+            .map(|mut layer| layer.remove(0))
+            .collect();
+        assert_eq!(alignments, model.gradient_alignment(&[0.3, 0.8], 0.5));
+    }
+
+    #[test]
+    fn test_multi_output() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut layer0 = MultiOutputLayer::<Sigmoid>::new(&mut rng, 3, 4, 5);
+        let mut layer1 = MultiOutputLayer::<PassThrough>::new(&mut rng, 3, 5, 1);
+        let mut model = MultiOutputSequential::builder(StdRng::seed_from_u64(42), 3, 2)
+            .encoder(Encoder::Duplicate)
+            .layer(Sigmoid::default(), 5)
+            .output_layer(PassThrough);
+
+        let inputs = [0.3, 0.8];
+        let x = layer0.forward(vec![vec![0.3, 0.3, 0.8, 0.8]; 3]);
+        let outputs: Vec<_> = layer1.forward(x).into_iter().map(|x| x[0]).collect();
+        assert_eq!(model.forward(&inputs), outputs);
+
+        let deltas = [0.1, -0.2, 0.3];
+        layer0.backward(&deltas);
+        layer1.backward(&deltas);
+        model.backward(&deltas);
+        let x = layer0.forward_without_train(vec![vec![0.3, 0.3, 0.8, 0.8]; 3]);
+        let outputs: Vec<_> = layer1
+            .forward_without_train(x)
+            .into_iter()
+            .map(|x| x[0])
+            .collect();
+        assert_eq!(model.forward_without_train(&inputs), outputs);
+    }
+
+    #[test]
+    fn test_multi_output_with_inputs() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let config = LayerConfig::default();
+        let mut layer0 =
+            MultiOutputLayer::with_inputs(&mut rng, &[4, 2], 3, Sigmoid::default(), config);
+        let mut layer1 = MultiOutputLayer::<PassThrough>::new(&mut rng, 2, 3, 1);
+        let mut model =
+            MultiOutputSequential::builder_with_inputs(StdRng::seed_from_u64(42), &[2, 1])
+                .encoder(Encoder::Duplicate)
+                .layer(Sigmoid::default(), 3)
+                .output_layer(PassThrough);
+
+        let inputs: [&[f64]; 2] = [&[0.3, 0.8], &[0.5]];
+        let encoded = || vec![vec![0.3, 0.3, 0.8, 0.8], vec![0.5, 0.5]];
+        let x = layer0.forward(encoded());
+        let outputs: Vec<_> = layer1.forward(x).into_iter().map(|x| x[0]).collect();
+        assert_eq!(model.forward_each(&inputs), outputs);
+
+        let deltas = [0.1, -0.2];
+        layer0.backward(&deltas);
+        layer1.backward(&deltas);
+        model.backward(&deltas);
+        let x = layer0.forward_without_train(encoded());
+        let outputs: Vec<_> = layer1
+            .forward_without_train(x)
+            .into_iter()
+            .map(|x| x[0])
+            .collect();
+        assert_eq!(model.forward_each_without_train(&inputs), outputs);
+        assert_eq!(model.forward_batch_each(&[&inputs]), vec![outputs]);
+    }
+}
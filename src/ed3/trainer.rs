@@ -0,0 +1,529 @@
+use super::{float::Float, loss_fn::Loss, schedule::Schedule};
+use crate::mnist::Sampler;
+
+/// Outputs of a model, and the deltas it takes back in the same shape.
+pub trait Signal: Clone {
+    fn scale(&self, rate: f64) -> Self;
+}
+
+impl<F: Float> Signal for F {
+    fn scale(&self, rate: f64) -> Self {
+        *self * F::cast(rate)
+    }
+}
+
+impl<F: Float> Signal for Vec<F> {
+    fn scale(&self, rate: f64) -> Self {
+        let rate = F::cast(rate);
+        self.iter().map(|&x| x * rate).collect()
+    }
+}
+
+/// `F` is the precision of the inputs.
+pub trait Model<F = f64> {
+    type Output: Signal;
+
+    fn forward(&mut self, inputs: &[F]) -> Self::Output;
+    fn forward_without_train(&self, inputs: &[F]) -> Self::Output;
+    fn backward(&mut self, delta: &Self::Output);
+    /// The rate of a schedule owned by the model, which then scales its own deltas. `Trainer`
+    /// reports this rate and leaves the deltas unscaled.
+    fn learning_rate(&self) -> Option<f64> {
+        None
+    }
+    /// Advances a schedule owned by the model, called by `Trainer` after every epoch or update,
+    /// see `ScheduleStep`.
+    fn step_schedule(&mut self, _metric: Option<f64>) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    Loss,
+    Accuracy,
+}
+
+/// Stops training once the monitored metric has not improved by more than `min_delta` for
+/// `patience` epochs. The validation metric is monitored when there is validation data, the
+/// training one otherwise.
+#[derive(Debug, Clone, Copy)]
+pub struct EarlyStopping {
+    pub metric: Metric,
+    pub patience: usize,
+    pub min_delta: f64,
+}
+
+impl EarlyStopping {
+    pub fn new(metric: Metric, patience: usize) -> Self {
+        EarlyStopping {
+            metric,
+            patience,
+            min_delta: 0.,
+        }
+    }
+}
+
+/// When `Trainer` steps its schedule and the model's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScheduleStep {
+    /// After every epoch, with the monitored loss.
+    #[default]
+    Epoch,
+    /// After every update, with the loss of the sample.
+    Update,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TrainerConfig {
+    pub epochs: usize,
+    /// Multiplies the deltas, unless the trainer has a schedule. `None` leaves them unscaled,
+    /// as a model with a schedule of its own requires. `RmsProp` and `Adam` normalize the
+    /// deltas, give the model a schedule to change their rate.
+    pub learning_rate: Option<f64>,
+    pub schedule_step: ScheduleStep,
+    pub early_stopping: Option<EarlyStopping>,
+    /// Draws the training samples of each epoch, in dataset order by default.
+    pub sampler: Sampler,
+}
+
+impl Default for TrainerConfig {
+    fn default() -> Self {
+        TrainerConfig {
+            epochs: 1,
+            learning_rate: None,
+            schedule_step: ScheduleStep::default(),
+            early_stopping: None,
+            sampler: Sampler::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EpochMetrics {
+    pub epoch: usize,
+    /// The rate at the start of the epoch.
+    pub learning_rate: f64,
+    /// Mean loss of the training samples, taken before their updates.
+    pub loss: f64,
+    pub accuracy: Option<f64>,
+    pub validation_loss: Option<f64>,
+    pub validation_accuracy: Option<f64>,
+}
+
+impl EpochMetrics {
+    fn monitored(&self, metric: Metric) -> f64 {
+        match metric {
+            Metric::Loss => self.validation_loss.unwrap_or(self.loss),
+            Metric::Accuracy => self
+                .validation_accuracy
+                .or(self.accuracy)
+                .expect("early stopping on accuracy needs an accuracy function"),
+        }
+    }
+}
+
+type AccuracyFn<'a, M, L, F> =
+    dyn Fn(&<M as Model<F>>::Output, &<L as Loss<<M as Model<F>>::Output>>::Target) -> bool + 'a;
+type Callback<'a, M> = dyn FnMut(&M, &EpochMetrics) + 'a;
+
+/// Trains a model sample by sample: forward, loss delta scaled by the learning rate, backward.
+pub struct Trainer<'a, M, L, F = f64>
+where
+    M: Model<F>,
+    L: Loss<M::Output>,
+{
+    loss: L,
+    config: TrainerConfig,
+    schedule: Option<Box<dyn Schedule>>,
+    accuracy: Option<Box<AccuracyFn<'a, M, L, F>>>,
+    callbacks: Vec<Box<Callback<'a, M>>>,
+}
+
+impl<'a, M, L, F> Trainer<'a, M, L, F>
+where
+    M: Model<F>,
+    L: Loss<M::Output>,
+{
+    pub fn new(loss: L, config: TrainerConfig) -> Self {
+        Trainer {
+            loss,
+            config,
+            schedule: None,
+            accuracy: None,
+            callbacks: Vec::new(),
+        }
+    }
+
+    /// Replaces the constant learning rate of the config, stepped as `ScheduleStep` says. A
+    /// model with a schedule of its own can't be trained with one.
+    pub fn schedule<S>(mut self, schedule: S) -> Self
+    where
+        S: Schedule + 'static,
+    {
+        self.schedule = Some(Box::new(schedule));
+        self
+    }
+
+    /// Tells whether an output is correct, to report accuracies.
+    pub fn accuracy<C>(mut self, is_correct: C) -> Self
+    where
+        C: Fn(&M::Output, &L::Target) -> bool + 'a,
+    {
+        self.accuracy = Some(Box::new(is_correct));
+        self
+    }
+
+    /// Called with the model and the metrics after every epoch.
+    pub fn callback<C>(mut self, callback: C) -> Self
+    where
+        C: FnMut(&M, &EpochMetrics) + 'a,
+    {
+        self.callbacks.push(Box::new(callback));
+        self
+    }
+
+    pub fn learning_rate(&self) -> f64 {
+        self.schedule
+            .as_ref()
+            .map_or(self.config.learning_rate.unwrap_or(1.), |schedule| {
+                schedule.learning_rate()
+            })
+    }
+
+    fn step_schedules(&mut self, model: &mut M, metric: f64) {
+        if let Some(schedule) = &mut self.schedule {
+            schedule.step(Some(metric));
+        }
+        model.step_schedule(Some(metric));
+    }
+
+    /// Returns the mean loss and, with an accuracy function, the accuracy.
+    pub fn evaluate<I>(&self, model: &M, data: &[(I, L::Target)]) -> (f64, Option<f64>)
+    where
+        I: AsRef<[F]>,
+    {
+        let mut sum_loss = 0.;
+        let mut correct_count = 0;
+        for (inputs, target) in data {
+            let output = model.forward_without_train(inputs.as_ref());
+            sum_loss += self.loss.loss(&output, target);
+            correct_count += self.is_correct(&output, target) as usize;
+        }
+        (
+            sum_loss / data.len() as f64,
+            self.accuracy
+                .as_ref()
+                .map(|_| correct_count as f64 / data.len() as f64),
+        )
+    }
+
+    /// Runs the epochs and returns the metrics of each, stopping early if configured. The deltas
+    /// are scaled by either the model's schedule or the trainer's rate, never both.
+    pub fn fit<I>(
+        &mut self,
+        model: &mut M,
+        train: &[(I, L::Target)],
+        validation: Option<&[(I, L::Target)]>,
+    ) -> Vec<EpochMetrics>
+    where
+        I: AsRef<[F]>,
+    {
+        let model_schedule = model.learning_rate().is_some();
+        assert!(
+            !model_schedule || (self.schedule.is_none() && self.config.learning_rate.is_none()),
+            "the model has its own schedule, don't give the trainer a learning rate"
+        );
+        let mut history = Vec::new();
+        let mut best = None;
+        let mut bad_epochs = 0;
+
+        for epoch in 0..self.config.epochs {
+            let learning_rate = model.learning_rate().unwrap_or(self.learning_rate());
+            let mut sum_loss = 0.;
+            let mut sample_count = 0;
+            let mut correct_count = 0;
+            for (inputs, target) in self.config.sampler.epoch(epoch, train) {
+                let output = model.forward(inputs.as_ref());
+                let delta_rate = if model_schedule {
+                    1.
+                } else {
+                    self.learning_rate()
+                };
+                let delta = self.loss.delta(&output, target).scale(delta_rate);
+                model.backward(&delta);
+
+                let loss = self.loss.loss(&output, target);
+                if self.config.schedule_step == ScheduleStep::Update {
+                    self.step_schedules(model, loss);
+                }
+                sum_loss += loss;
+                correct_count += self.is_correct(&output, target) as usize;
+                sample_count += 1;
+            }
+
+            let validation = validation.map(|data| self.evaluate(model, data));
+            let metrics = EpochMetrics {
+                epoch,
+                learning_rate,
+                loss: sum_loss / sample_count as f64,
+                accuracy: self
+                    .accuracy
+                    .as_ref()
+                    .map(|_| correct_count as f64 / sample_count as f64),
+                validation_loss: validation.map(|(loss, _)| loss),
+                validation_accuracy: validation.and_then(|(_, accuracy)| accuracy),
+            };
+
+            if self.config.schedule_step == ScheduleStep::Epoch {
+                self.step_schedules(model, metrics.monitored(Metric::Loss));
+            }
+            for callback in self.callbacks.iter_mut() {
+                callback(model, &metrics);
+            }
+            history.push(metrics);
+
+            if let Some(early_stopping) = &self.config.early_stopping {
+                let value = metrics.monitored(early_stopping.metric);
+                let improved = best.is_none_or(|best: f64| match early_stopping.metric {
+                    Metric::Loss => value < best - early_stopping.min_delta,
+                    Metric::Accuracy => value > best + early_stopping.min_delta,
+                });
+                if improved {
+                    best = Some(value);
+                    bad_epochs = 0;
+                } else {
+                    bad_epochs += 1;
+                    if bad_epochs >= early_stopping.patience {
+                        break;
+                    }
+                }
+            }
+        }
+
+        history
+    }
+
+    fn is_correct(&self, output: &M::Output, target: &L::Target) -> bool {
+        self.accuracy
+            .as_ref()
+            .is_some_and(|is_correct| is_correct(output, target))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        differentiable_fn::{DifferentiableFn, Sigmoid},
+        gate::Gate,
+        loss_fn::{BCELoss, BCEWithLogitsLoss, SampleWeighted},
+        mnist::Mnist,
+        schedule::StepDecay,
+    };
+    use super::*;
+    use crate::mnist::Sampling;
+    use std::cell::RefCell;
+
+    fn xor() -> Vec<(Vec<f64>, f64)> {
+        vec![
+            (vec![0., 0.], 0.),
+            (vec![0., 1.], 1.),
+            (vec![1., 0.], 1.),
+            (vec![1., 1.], 0.),
+        ]
+    }
+
+    #[test]
+    fn test_same_as_loop() {
+        let train = xor();
+        let mut xor = Gate::<Sigmoid>::new();
+        let mut losses = Vec::new();
+        for _ in 0..20 {
+            let mut loss = 0.;
+            for (inputs, target) in train.iter() {
+                let output = xor.forward(inputs);
+                xor.backward(BCELoss.derivative((output, *target)) * 0.5);
+                loss += BCELoss.eval((output, *target));
+            }
+            losses.push(loss / 4.);
+        }
+
+        let mut model = Gate::<Sigmoid>::new();
+        let config = TrainerConfig {
+            epochs: 20,
+            learning_rate: Some(0.5),
+            ..Default::default()
+        };
+        let history = Trainer::new(BCELoss, config).fit(&mut model, &train, None);
+        let trainer_losses: Vec<_> = history.iter().map(|metrics| metrics.loss).collect();
+        assert_eq!(trainer_losses, losses);
+        assert_eq!(model.forward(&[1., 0.]), xor.forward(&[1., 0.]));
+    }
+
+    #[test]
+    fn test_model_schedule() {
+        // The model's schedule alone sets the step size, and the metrics report it.
+        let train = xor();
+        let mut xor = Gate::<Sigmoid>::new();
+        let mut losses = Vec::new();
+        for epoch in 0..3 {
+            let learning_rate = 0.5 * 0.5f64.powi(epoch);
+            let mut loss = 0.;
+            for (inputs, target) in train.iter() {
+                let output = xor.forward(inputs);
+                xor.backward(BCELoss.derivative((output, *target)) * learning_rate);
+                loss += BCELoss.eval((output, *target));
+            }
+            losses.push(loss / 4.);
+        }
+
+        let mut model = Gate::<Sigmoid>::new();
+        model.set_schedule(StepDecay::new(0.5, 0.5, 1));
+        let config = TrainerConfig {
+            epochs: 3,
+            ..Default::default()
+        };
+        let history = Trainer::new(BCELoss, config).fit(&mut model, &train, None);
+        let learning_rates: Vec<_> = history.iter().map(|m| m.learning_rate).collect();
+        assert_eq!(learning_rates, [0.5, 0.25, 0.125]);
+        let trainer_losses: Vec<_> = history.iter().map(|metrics| metrics.loss).collect();
+        assert_eq!(trainer_losses, losses);
+        assert_eq!(model.forward(&[1., 0.]), xor.forward(&[1., 0.]));
+    }
+
+    #[test]
+    fn test_schedule_per_update() {
+        let train = xor();
+        let mut xor = Gate::<Sigmoid>::new();
+        for (i, (inputs, target)) in train.iter().enumerate() {
+            let output = xor.forward(inputs);
+            xor.backward(BCELoss.derivative((output, *target)) * 0.5 * 0.5f64.powi(i as i32));
+        }
+
+        let mut model = Gate::<Sigmoid>::new();
+        let config = TrainerConfig {
+            schedule_step: ScheduleStep::Update,
+            ..Default::default()
+        };
+        let mut trainer = Trainer::new(BCELoss, config).schedule(StepDecay::new(0.5, 0.5, 1));
+        let history = trainer.fit(&mut model, &train, None);
+        assert_eq!(history[0].learning_rate, 0.5);
+        assert_eq!(trainer.learning_rate(), 0.5 * 0.5f64.powi(4));
+        assert_eq!(model.forward(&[1., 0.]), xor.forward(&[1., 0.]));
+    }
+
+    #[test]
+    #[should_panic(expected = "its own schedule")]
+    fn test_model_schedule_and_rate() {
+        let mut model = Gate::<Sigmoid>::new();
+        model.set_schedule(StepDecay::new(0.5, 0.5, 1));
+        let config = TrainerConfig {
+            learning_rate: Some(1.),
+            ..Default::default()
+        };
+        Trainer::new(BCELoss, config).fit(&mut model, &xor(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "its own schedule")]
+    fn test_two_schedules() {
+        let mut model = Gate::<Sigmoid>::new();
+        model.set_schedule(StepDecay::new(0.5, 0.5, 1));
+        Trainer::new(BCELoss, TrainerConfig::default())
+            .schedule(StepDecay::new(0.5, 0.5, 1))
+            .fit(&mut model, &xor(), None);
+    }
+
+    #[test]
+    fn test_sampler() {
+        let train = xor();
+        let fit = |sampler| {
+            let config = TrainerConfig {
+                epochs: 5,
+                sampler,
+                ..Default::default()
+            };
+            let mut model = Gate::<Sigmoid>::new();
+            Trainer::new(BCELoss, config).fit(&mut model, &train, None)
+        };
+        let shuffled = fit(Sampler::new(Sampling::Shuffle, 42));
+        assert_eq!(shuffled, fit(Sampler::new(Sampling::Shuffle, 42)));
+        assert_ne!(shuffled, fit(Sampler::default()));
+    }
+
+    #[test]
+    fn test_metrics_and_early_stopping() {
+        let train = xor();
+        let epochs = RefCell::new(Vec::new());
+        let config = TrainerConfig {
+            epochs: 1000,
+            early_stopping: Some(EarlyStopping::new(Metric::Accuracy, 3)),
+            ..Default::default()
+        };
+        let mut trainer = Trainer::new(BCELoss, config)
+            .schedule(StepDecay::new(0.5, 0.5, 2))
+            .accuracy(|output: &f64, target: &f64| (*output > 0.5) == (*target > 0.5))
+            .callback(|_, metrics| epochs.borrow_mut().push(metrics.epoch));
+
+        let mut model = Gate::<Sigmoid>::new();
+        let history = trainer.fit(&mut model, &train, Some(&train));
+        drop(trainer);
+
+        let learning_rates: Vec<_> = history.iter().map(|m| m.learning_rate).collect();
+        assert_eq!(learning_rates[..4], [0.5, 0.5, 0.25, 0.25]);
+        assert!(history.len() < 1000);
+        assert_eq!(epochs.into_inner(), (0..history.len()).collect::<Vec<_>>());
+        let last = history.last().unwrap();
+        assert!(last.validation_loss.is_some() && last.validation_accuracy.is_some());
+    }
+
+    #[test]
+    fn test_sample_weights() {
+        let train = xor();
+        let fit = |weight: f64| {
+            let weighted: Vec<_> = train
+                .iter()
+                .map(|(inputs, target)| (inputs.clone(), (*target, weight)))
+                .collect();
+            let config = TrainerConfig {
+                epochs: 5,
+                ..Default::default()
+            };
+            let mut model = Gate::<Sigmoid>::new();
+            Trainer::new(SampleWeighted(BCELoss), config).fit(&mut model, &weighted, None)
+        };
+        let config = TrainerConfig {
+            epochs: 5,
+            ..Default::default()
+        };
+        let unweighted =
+            Trainer::new(BCELoss, config).fit(&mut Gate::<Sigmoid>::new(), &train, None);
+        assert_eq!(fit(1.), unweighted);
+        assert_eq!(fit(0.)[4].loss, 0.);
+    }
+
+    #[test]
+    fn test_f32() {
+        // The same model in single precision follows the f64 one.
+        let train: Vec<_> = (0..4)
+            .map(|i| (vec![0.2 * i as f64; 784], (i % 2) as f64))
+            .collect();
+        let config = TrainerConfig {
+            epochs: 10,
+            learning_rate: Some(0.02),
+            ..Default::default()
+        };
+        let double =
+            Trainer::new(BCEWithLogitsLoss, config).fit(&mut Mnist::new(1, 4), &train, None);
+        let train: Vec<_> = train
+            .iter()
+            .map(|(inputs, target)| {
+                let inputs: Vec<_> = inputs.iter().map(|&x| x as f32).collect();
+                (inputs, *target as f32)
+            })
+            .collect();
+        let single =
+            Trainer::new(BCEWithLogitsLoss, config).fit(&mut Mnist::<f32>::new(1, 4), &train, None);
+        for (single, double) in single.iter().zip(&double) {
+            assert!((single.loss - double.loss).abs() < 1e-4 * double.loss);
+        }
+    }
+}
@@ -15,3 +15,17 @@ where
         .filter(|(i, _)| i % 2 == 0)
         .map(|(_, &n)| n)
 }
+
+// SplitMix64, a bijective scrambler of 64-bit values.
+fn split_mix(x: u64) -> u64 {
+    let x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+// Derives the seed of stream `stream` (an epoch, a layer, a sub-network) from `seed`. Unlike
+// `seed + stream`, neighbouring seeds don't share streams.
+pub(crate) fn mix_seed(seed: u64, stream: u64) -> u64 {
+    split_mix(split_mix(seed).wrapping_add(stream))
+}
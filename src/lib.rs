@@ -1,9 +1,30 @@
 mod ed3;
 pub mod mnist;
 
-pub use ed3::gate::Gate;
-pub use ed3::layer::{Layer, MultiOutputLayer};
-pub use ed3::mnist::Mnist;
+pub use ed3::alignment::{Alignment, LayerAlignment};
+pub use ed3::float::Float;
+pub use ed3::gate::{Gate, GateState};
+pub use ed3::initializer::Initializer;
+pub use ed3::layer::{
+    Amines, BatchReduction, Layer, LayerConfig, LayerState, Layout, MultiOutputLayer,
+    MultiOutputLayerState, Noise, SignConstraint, WeightDecay,
+};
+pub use ed3::mnist::{Mnist, MnistState};
+pub use ed3::optimizer::{Adam, Momentum, Optimizer, RmsProp, Sgd};
+pub use ed3::persistence::{
+    check_dims, Format, ModelError, Persist, PersistentActivation, VersionedState,
+};
+pub use ed3::recurrent::{Recurrent, RecurrentConfig};
+pub use ed3::schedule::{
+    Constant, Cosine, Exponential, ReduceOnPlateau, Schedule, StepDecay, Warmup,
+};
+pub use ed3::sequential::{
+    Encoder, Module, MultiOutputModule, MultiOutputSequential, MultiOutputSequentialBuilder,
+    Sequential, SequentialBuilder,
+};
+pub use ed3::trainer::{
+    EarlyStopping, EpochMetrics, Metric, Model, ScheduleStep, Signal, Trainer, TrainerConfig,
+};
 pub use ed3::util::{duplicate_elements, unduplicate_elements};
 pub use ed3::{differentiable_fn::*, loss_fn::*};
 pub use mnist as dataset;
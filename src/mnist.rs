@@ -1,3 +1,5 @@
+use crate::{ed3::util::mix_seed, Float};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
 use std::fs::File;
 use std::io::{self, Read};
 use std::path::Path;
@@ -19,7 +21,7 @@ fn read_labels<P: AsRef<Path>>(path: P) -> io::Result<Vec<u8>> {
     Ok(labels)
 }
 
-fn read_images_labels<P: AsRef<Path>>(path: P) -> io::Result<Vec<Vec<f64>>> {
+fn read_images_labels<P: AsRef<Path>, F: Float>(path: P) -> io::Result<Vec<Vec<F>>> {
     let mut images = Vec::new();
 
     let mut file = File::open(path)?;
@@ -50,7 +52,7 @@ fn read_images_labels<P: AsRef<Path>>(path: P) -> io::Result<Vec<Vec<f64>>> {
         .map(|image| {
             image
                 .into_iter()
-                .map(|pixel| pixel as f64 / 255.0)
+                .map(|pixel| F::cast(pixel as f64 / 255.0))
                 .collect()
         })
         .collect();
@@ -58,19 +60,23 @@ fn read_images_labels<P: AsRef<Path>>(path: P) -> io::Result<Vec<Vec<f64>>> {
     Ok(images)
 }
 
-fn read_dataset<P: AsRef<Path>>(labels_path: P, images_path: P) -> io::Result<Vec<(u8, Vec<f64>)>> {
+fn read_dataset<P: AsRef<Path>, F: Float>(
+    labels_path: P,
+    images_path: P,
+) -> io::Result<Vec<(u8, Vec<F>)>> {
     let labels = read_labels(labels_path)?;
     let images = read_images_labels(images_path)?;
 
     Ok(labels.into_iter().zip(images.into_iter()).collect())
 }
 
-pub struct Mnist {
-    pub train: Vec<(u8, Vec<f64>)>,
-    pub test: Vec<(u8, Vec<f64>)>,
+/// The pixels are scaled to `[0, 1]` in the precision `F`.
+pub struct Mnist<F = f64> {
+    pub train: Vec<(u8, Vec<F>)>,
+    pub test: Vec<(u8, Vec<F>)>,
 }
 
-pub fn read_mnist() -> Mnist {
+pub fn read_mnist<F: Float>() -> Mnist<F> {
     let train = read_dataset(
         "mnist/train-labels.idx1-ubyte",
         "mnist/train-images.idx3-ubyte",
@@ -84,3 +90,96 @@ pub fn read_mnist() -> Mnist {
 
     Mnist { train, test }
 }
+
+/// How the samples of an epoch are drawn from a dataset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sampling {
+    /// Every sample once, in dataset order.
+    InOrder,
+    /// Every sample once, in a new order each epoch.
+    Shuffle,
+    /// The given number of distinct samples each epoch.
+    WithoutReplacement(usize),
+    /// The given number of samples each epoch, drawn independently.
+    WithReplacement(usize),
+}
+
+/// Draws the samples of each epoch. The order of an epoch only depends on the seed and the
+/// epoch index, so a run can be reproduced or resumed at any epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sampler {
+    pub sampling: Sampling,
+    pub seed: u64,
+}
+
+impl Sampler {
+    pub const fn new(sampling: Sampling, seed: u64) -> Self {
+        Sampler { sampling, seed }
+    }
+
+    pub fn indices(&self, epoch: usize, len: usize) -> Vec<usize> {
+        let mut rng = StdRng::seed_from_u64(mix_seed(self.seed, epoch as u64));
+        match self.sampling {
+            Sampling::InOrder => (0..len).collect(),
+            Sampling::Shuffle => {
+                let mut indices: Vec<_> = (0..len).collect();
+                indices.shuffle(&mut rng);
+                indices
+            }
+            Sampling::WithoutReplacement(count) => {
+                assert!(count <= len, "cannot draw {} of {} samples", count, len);
+                rand::seq::index::sample(&mut rng, len, count).into_vec()
+            }
+            Sampling::WithReplacement(count) => {
+                assert!(len > 0 || count == 0, "cannot draw from an empty dataset");
+                (0..count).map(|_| rng.gen_range(0..len)).collect()
+            }
+        }
+    }
+
+    pub fn epoch<'a, T>(&self, epoch: usize, data: &'a [T]) -> impl Iterator<Item = &'a T> {
+        self.indices(epoch, data.len())
+            .into_iter()
+            .map(move |i| &data[i])
+    }
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Sampler::new(Sampling::InOrder, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sampler() {
+        let data: Vec<_> = (0..10).collect();
+        let in_order = Sampler::default();
+        assert!(in_order.epoch(3, &data).copied().eq(0..10));
+
+        let shuffle = Sampler::new(Sampling::Shuffle, 42);
+        let mut first = shuffle.indices(0, 10);
+        assert_eq!(first, shuffle.indices(0, 10));
+        assert_ne!(first, shuffle.indices(1, 10));
+        assert_ne!(first, Sampler::new(Sampling::Shuffle, 43).indices(0, 10));
+        // Neighbouring seeds don't replay each other's epochs.
+        assert_ne!(
+            shuffle.indices(1, 10),
+            Sampler::new(Sampling::Shuffle, 43).indices(0, 10)
+        );
+        first.sort();
+        assert_eq!(first, data);
+
+        let mut without = Sampler::new(Sampling::WithoutReplacement(6), 42).indices(0, 10);
+        without.sort();
+        without.dedup();
+        assert_eq!(without.len(), 6);
+
+        let with = Sampler::new(Sampling::WithReplacement(100), 42).indices(0, 10);
+        assert_eq!(with.len(), 100);
+        assert!(with.iter().all(|&i| i < 10));
+    }
+}
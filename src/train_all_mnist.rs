@@ -1,88 +1,205 @@
-use ed::{dataset, duplicate_elements, CrossEntropyLoss, MultiOutputLayer, PassThrough, Sigmoid};
+use ed::{
+    check_dims,
+    dataset::{self, Sampler, Sampling},
+    duplicate_elements, Constant, Cosine, CrossEntropyLoss, Exponential, Float, Format, Loss,
+    ModelError, MultiOutputLayer, MultiOutputLayerState, Noise, PassThrough, Persist,
+    PersistentActivation, ReduceOnPlateau, Schedule, Sigmoid, StepDecay, Warmup, WeightDecay,
+};
 use plotters::prelude::*;
 use rand::{rngs::StdRng, SeedableRng};
 
+// The precision of the model and the images, `f32` halves their memory.
+type Precision = f64;
+
 const LEARNING_RATE: f64 = 0.02;
+const EPOCHS: usize = 100;
+const PARALLEL: bool = false;
+const MODEL_PATH: &str = "train_all_mnist.model";
+const WEIGHT_DECAY: WeightDecay = WeightDecay { l1: 0., l2: 0. };
+const NOISE: Noise = Noise {
+    input_dropout: 0.,
+    output_dropout: 0.,
+    input_std: 0.,
+};
+const SAMPLER: Sampler = Sampler::new(Sampling::InOrder, 42);
 
 pub struct Mnist {
-    layer0: MultiOutputLayer<Sigmoid>,
-    last_layer: MultiOutputLayer<PassThrough>,
+    layer0: MultiOutputLayer<Sigmoid, Precision>,
+    last_layer: MultiOutputLayer<PassThrough, Precision>,
 }
 
 impl Mnist {
     fn new() -> Self {
         let mut rng = StdRng::seed_from_u64(42);
-        Mnist {
+        let mut mnist = Mnist {
             layer0: MultiOutputLayer::new(&mut rng, 10, 784 * 2, 4),
             last_layer: MultiOutputLayer::new(&mut rng, 10, 4, 1),
-        }
+        };
+        mnist.configure();
+        mnist
+    }
+
+    // Applies the training settings, which are not saved with the weights.
+    fn configure(&mut self) {
+        self.layer0.set_parallel(PARALLEL);
+        self.last_layer.set_parallel(PARALLEL);
+        self.layer0.set_weight_decay(WEIGHT_DECAY);
+        self.last_layer.set_weight_decay(WEIGHT_DECAY);
+        self.layer0.set_noise(NOISE, 0);
+        self.last_layer.set_noise(NOISE, 1);
     }
 
-    fn forward(&mut self, inputs: &[f64]) -> Vec<f64> {
-        let x = duplicate_elements(inputs.into_iter()).collect();
+    fn forward(&mut self, inputs: &[Precision]) -> Vec<Precision> {
+        let x = duplicate_elements(inputs.iter()).collect();
         let x = vec![x; 10];
         let x = self.layer0.forward(x);
         let x = self.last_layer.forward(x);
         x.into_iter().map(|x| x[0]).collect()
     }
 
-    fn forward_without_train(&self, inputs: &[f64]) -> Vec<f64> {
-        let x = duplicate_elements(inputs.into_iter()).collect();
+    fn forward_without_train(&self, inputs: &[Precision]) -> Vec<Precision> {
+        let x = duplicate_elements(inputs.iter()).collect();
         let x = vec![x; 10];
         let x = self.layer0.forward_without_train(x);
         let x = self.last_layer.forward_without_train(x);
         x.into_iter().map(|x| x[0]).collect()
     }
 
-    fn backward(&mut self, deltas: Vec<f64>) {
+    fn backward(&mut self, deltas: Vec<Precision>) {
         self.layer0.backward(&deltas);
         self.last_layer.backward(&deltas);
     }
+
+    // Both layers follow their own copy of the schedule named `name`, see `schedule`.
+    fn set_schedule(&mut self, name: Option<&str>) {
+        self.layer0.set_schedule(schedule(name));
+        self.last_layer.set_schedule(schedule(name));
+    }
+
+    fn step_schedule(&mut self, metric: Option<f64>) {
+        self.layer0.step_schedule(metric);
+        self.last_layer.step_schedule(metric);
+    }
+}
+
+impl Persist for Mnist {
+    const MODEL: &'static str = "train_all_mnist::Mnist";
+    const PRECISION: &'static str = Precision::NAME;
+    type State = (
+        MultiOutputLayerState<Sigmoid, Precision>,
+        MultiOutputLayerState<PassThrough, Precision>,
+    );
+
+    fn activations() -> Vec<&'static str> {
+        vec![Sigmoid::NAME, PassThrough::NAME]
+    }
+
+    fn state(&self) -> Self::State {
+        (self.layer0.state(), self.last_layer.state())
+    }
+
+    fn from_state((layer0, last_layer): Self::State) -> Result<Self, ModelError> {
+        let layer0 = MultiOutputLayer::from_state(layer0)?;
+        let last_layer = MultiOutputLayer::from_state(last_layer)?;
+        if (layer0.last_output(), last_layer.last_output()) != (10, 10) {
+            return Err(ModelError::ArchitectureMismatch(format!(
+                "{} and {} sub-networks, expected 10",
+                layer0.last_output(),
+                last_layer.last_output()
+            )));
+        }
+        for (i, (input, output)) in layer0
+            .inputs()
+            .into_iter()
+            .zip(layer0.outputs())
+            .enumerate()
+        {
+            check_dims(&format!("layer0[{}]", i), (input, output), (784 * 2, 4))?;
+        }
+        for (i, (input, output)) in last_layer
+            .inputs()
+            .into_iter()
+            .zip(last_layer.outputs())
+            .enumerate()
+        {
+            check_dims(&format!("last_layer[{}]", i), (input, output), (4, 1))?;
+        }
+        let mut mnist = Mnist { layer0, last_layer };
+        mnist.configure();
+        Ok(mnist)
+    }
 }
 
-fn one_hot_encoding(label: u8) -> Vec<f64> {
+fn one_hot_encoding(label: u8) -> Vec<Precision> {
     let mut v = vec![0.; 10];
     v[label as usize] = 1.;
     v
 }
 
-fn one_hot_encoding_all_labels(data: Vec<(u8, Vec<f64>)>) -> Vec<(u8, Vec<f64>, Vec<f64>)> {
+fn one_hot_encoding_all_labels(
+    data: Vec<(u8, Vec<Precision>)>,
+) -> Vec<(u8, Vec<Precision>, Vec<Precision>)> {
     data.into_iter()
         .map(|(label, image)| (label, one_hot_encoding(label), image))
         .collect()
 }
 
+// Picks the per-epoch learning-rate schedule from the first non-flag argument, a constant rate
+// by default.
+fn schedule(name: Option<&str>) -> Box<dyn Schedule> {
+    match name {
+        None | Some("constant") => Box::new(Constant::new(LEARNING_RATE)),
+        Some("step") => Box::new(StepDecay::new(LEARNING_RATE, 0.5, 25)),
+        Some("exponential") => Box::new(Exponential::new(LEARNING_RATE, 0.97)),
+        Some("cosine") => Box::new(Warmup::new(5, Cosine::new(LEARNING_RATE, 0., EPOCHS - 5))),
+        Some("plateau") => Box::new(ReduceOnPlateau::new(LEARNING_RATE, 0.5, 3)),
+        Some(name) => panic!("unknown schedule {}", name),
+    }
+}
+
 fn main() {
-    let mut model = Mnist::new();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    // `--resume` continues from `MODEL_PATH`, `--save` writes it after every epoch.
+    let (flags, names): (Vec<_>, Vec<_>) = args.iter().partition(|arg| arg.starts_with("--"));
+    if let Some(flag) = flags
+        .iter()
+        .find(|flag| !["--resume", "--save"].contains(&&flag[..]))
+    {
+        panic!("unknown flag {}", flag);
+    }
+    let resume = flags.iter().any(|flag| *flag == "--resume");
+    let save = flags.iter().any(|flag| *flag == "--save");
+    let mut model = if resume {
+        println!("loading {}", MODEL_PATH);
+        Mnist::load(MODEL_PATH).expect("Failed to load model")
+    } else {
+        Mnist::new()
+    };
+    model.set_schedule(names.first().map(|name| name.as_str()));
     let mnist = dataset::read_mnist();
 
     let train = one_hot_encoding_all_labels(mnist.train);
     let test = mnist.test;
 
-    let train_len = train.len();
-
     let mut losses = vec![];
     let mut accuracies = vec![];
     let mut test_accuracies = vec![];
 
-    for _ in 0..100 {
+    for epoch in 0..EPOCHS {
+        let samples: Vec<_> = SAMPLER.epoch(epoch, &train).collect();
+        let train_len = samples.len();
         let mut sum_loss = 0.;
         let mut correct_count = 0;
 
-        for (i, (label, encoded_label, image)) in train.iter().enumerate() {
+        for (i, (label, encoded_label, image)) in samples.into_iter().enumerate() {
             if i % 10000 == 0 {
                 println!("{} / {}", i, train_len);
             }
             let output = model.forward(image);
-            let deltas = CrossEntropyLoss::derivative((&output, encoded_label));
-            model.backward(
-                deltas
-                    .into_iter()
-                    .map(|delta| delta * LEARNING_RATE)
-                    .collect(),
-            );
-
-            let loss = CrossEntropyLoss::eval((&output, encoded_label));
+            let deltas = CrossEntropyLoss.delta(&output, encoded_label);
+            model.backward(deltas);
+
+            let loss = CrossEntropyLoss.loss(&output, encoded_label);
             sum_loss += loss;
 
             let output = CrossEntropyLoss::softmax(&output);
@@ -129,6 +246,13 @@ fn main() {
         losses.push(sum_loss / train_len as f64);
         accuracies.push(accuracy);
         test_accuracies.push(test_accuracy);
+        model.step_schedule(Some(loss));
+
+        if save {
+            model
+                .save(MODEL_PATH, Format::Binary)
+                .expect("Failed to save model");
+        }
     }
 
     let root = BitMapBackend::new("plot.png", (1080, 720)).into_drawing_area();
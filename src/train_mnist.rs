@@ -1,22 +1,55 @@
-use ed::{dataset, BCEWithLogitsLoss, DifferentiableFn, Mnist};
+use ed::{
+    dataset::{self, Sampler, Sampling},
+    inverse_frequency_weights, BCEWithLogitsLoss, ClassWeighted, FocalLoss, HingeLoss, Loss, Mnist,
+    SquaredHingeLoss,
+};
 use plotters::prelude::*;
 
-const LEARNING_RATE: f64 = 0.02;
+// The precision of the model and the images, `f32` halves their memory.
+type Precision = f64;
+
+const LEARNING_RATE: Precision = 0.02;
 const FIRST: u8 = 4;
 const SECOND: u8 = 9;
+const SAMPLER: Sampler = Sampler::new(Sampling::InOrder, 42);
+// Weights the classes inversely to their counts in the training data.
+const BALANCE_CLASSES: bool = false;
 
-fn filter_two_value(dataset: Vec<(u8, Vec<f64>)>) -> Vec<(u8, Vec<f64>)> {
+fn filter_two_value(dataset: Vec<(u8, Vec<Precision>)>) -> Vec<(u8, Vec<Precision>)> {
     dataset
         .into_iter()
         .filter(|(label, _)| *label == FIRST || *label == SECOND)
         .collect()
 }
 
-fn float_label(label: u8) -> f64 {
+type BoxedLoss = Box<dyn Loss<Precision, Target = Precision>>;
+
+// Picks the loss from the first argument, `bce` by default, along with the target of `FIRST`:
+// the hinge losses take targets of -1 and 1 instead of 0 and 1.
+fn loss_fn(name: Option<&str>, class_weights: Vec<f64>) -> (BoxedLoss, Precision) {
+    match name {
+        None | Some("bce") => (
+            Box::new(ClassWeighted::new(BCEWithLogitsLoss, class_weights)),
+            0.,
+        ),
+        Some("focal") => (
+            Box::new(ClassWeighted::new(FocalLoss::default(), class_weights)),
+            0.,
+        ),
+        Some("hinge") => (Box::new(ClassWeighted::new(HingeLoss, class_weights)), -1.),
+        Some("squared_hinge") => (
+            Box::new(ClassWeighted::new(SquaredHingeLoss, class_weights)),
+            -1.,
+        ),
+        Some(name) => panic!("unknown loss {}", name),
+    }
+}
+
+fn float_label(label: u8, first: Precision) -> Precision {
     if label == SECOND {
         1.
     } else {
-        0.
+        first
     }
 }
 
@@ -24,13 +57,18 @@ fn bool_label(label: u8) -> bool {
     label == SECOND
 }
 
-fn run_test(model: &Mnist, test: &Vec<(u8, Vec<f64>)>) {
+// Outputs above the midpoint of the two targets are classified as `SECOND`.
+fn is_second(output: Precision, first: Precision) -> bool {
+    output > (first + 1.) / 2.
+}
+
+fn run_test(model: &Mnist<Precision>, test: &[(u8, Vec<Precision>)], first: Precision) {
     let test_len = test.len();
     let correct_count = test
         .iter()
         .filter(|(label, image)| {
             let output = model.forward_without_train(image);
-            (output > 0.5) == bool_label(*label)
+            is_second(output, first) == bool_label(*label)
         })
         .count();
 
@@ -43,7 +81,7 @@ fn run_test(model: &Mnist, test: &Vec<(u8, Vec<f64>)>) {
 }
 
 fn main() {
-    let mut model = Mnist::new(1, 4);
+    let mut model = Mnist::<Precision>::new(1, 4);
     let mnist = dataset::read_mnist();
 
     let train: Vec<_> = filter_two_value(mnist.train);
@@ -60,21 +98,32 @@ fn main() {
         train_len - first_count
     );
 
-    run_test(&model, &test);
+    let class_weights = if BALANCE_CLASSES {
+        let labels = train.iter().map(|(label, _)| bool_label(*label) as usize);
+        inverse_frequency_weights(labels, 2)
+    } else {
+        vec![1.; 2]
+    };
+    println!("class weights: {:?}", class_weights);
+    let name = std::env::args().nth(1);
+    let (loss_fn, first) = loss_fn(name.as_deref(), class_weights);
+
+    run_test(&model, &test, first);
 
     let mut losses = vec![];
     let mut accuracies = vec![];
 
-    for _ in 0..10 {
+    for epoch in 0..10 {
+        let samples: Vec<_> = SAMPLER.epoch(epoch, &train).collect();
         let mut sum_loss = 0.;
 
-        for (label, image) in train.iter() {
-            let label = float_label(*label);
+        for (label, image) in samples.iter() {
+            let label = float_label(*label, first);
             let output = model.forward(image);
-            let delta = BCEWithLogitsLoss::derivative((output, label));
+            let delta = loss_fn.delta(&output, &label);
             model.backward(delta * LEARNING_RATE);
 
-            let l = BCEWithLogitsLoss::eval((output, label)).abs();
+            let l = loss_fn.loss(&output, &label).abs();
             sum_loss += l;
         }
 
@@ -82,22 +131,22 @@ fn main() {
             .iter()
             .filter(|(label, image)| {
                 let output = model.forward(image);
-                (output > 0.5) == bool_label(*label)
+                is_second(output, first) == bool_label(*label)
             })
             .count();
 
-        let loss = sum_loss / train_len as f64;
+        let loss = sum_loss / samples.len() as f64;
         let accuracy = correct_count as f64 / train_len as f64;
         println!(
             "loss: {:.8}, correct: {} / {} = {}",
             loss, correct_count, train_len, accuracy
         );
 
-        losses.push(sum_loss / train_len as f64);
+        losses.push(loss);
         accuracies.push(accuracy);
     }
 
-    run_test(&model, &test);
+    run_test(&model, &test, first);
 
     let root = BitMapBackend::new("plot.png", (1080, 720)).into_drawing_area();
     root.fill(&WHITE).unwrap();